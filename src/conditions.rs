@@ -0,0 +1,388 @@
+use hyper::server::Request;
+use hyper::StatusCode;
+use percent_encoding::percent_decode;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str;
+
+/// Conditions are requirements on the headers or the query of a request, besides its method and
+/// path, for a route to handle it; several routes may then share a method and path, the first
+/// whose conditions the request satisfies handling it, eg. to version an API by media type.
+///
+/// See `RouteBuilder::when` for how routes are registered along with conditions, and how requests
+/// satisfying no route's conditions are answered.
+///
+/// Conditions are compared as a set, regardless of the order they were added in.
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Condition {
+    // the media type, in lowercase and without parameters, the request must accept
+    Accept(String),
+    // the media type, in lowercase and without parameters, the body of the request must have
+    ContentType(String),
+    // the name, in lowercase, and value of a header the request must have
+    Header(String, String),
+    // the name of a query parameter the request must have, and the value it must have if any
+    Query(String, Option<String>),
+}
+
+impl Conditions {
+    pub fn new() -> Self {
+        Conditions::default()
+    }
+
+    /// Requires the request to accept the media type, eg. `application/vnd.api.v2+json`, as
+    /// listed by its `Accept` header including ranges such as `application/*`. A request without
+    /// an `Accept` header accepts any media type.
+    ///
+    /// A request accepting the media type only through a range or by having no `Accept` header is
+    /// handled by the route only if no route it satisfies otherwise shares its method and path,
+    /// such as one without conditions, see `RouteBuilder::when`.
+    pub fn accept(mut self, media_type: &str) -> Self {
+        self.conditions.push(Condition::Accept(essence(media_type)));
+        self
+    }
+
+    /// Requires the `Content-Type` of the request to be the media type, eg. `application/json`,
+    /// regardless of its parameters such as `charset`. The media type may be a range such as
+    /// `text/*`.
+    pub fn content_type(mut self, media_type: &str) -> Self {
+        self.conditions
+            .push(Condition::ContentType(essence(media_type)));
+        self
+    }
+
+    /// Requires the request to have the header with exactly the value, eg. `X-API-Version: 2`.
+    /// The name of the header is case-insensitive.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.conditions
+            .push(Condition::Header(name.to_ascii_lowercase(), value.to_owned()));
+        self
+    }
+
+    /// Requires the query of the request to have the parameter with exactly the value once
+    /// percent-decoded, eg. `type=user` for `/search?type=user`.
+    ///
+    /// Unlike other conditions, a request not satisfying the query conditions of any route
+    /// sharing the path is handled as if no route matched it. The value is available to the
+    /// handler using `RequestData::query_param`.
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.conditions
+            .push(Condition::Query(name.to_owned(), Some(value.to_owned())));
+        self
+    }
+
+    /// Requires the query of the request to have the parameter, with any value, eg. `q` for
+    /// `/search?q=lars`. The value is available to the handler using `RequestData::query_param`.
+    pub fn query_param(mut self, name: &str) -> Self {
+        self.conditions
+            .push(Condition::Query(name.to_owned(), None));
+        self
+    }
+
+    /// Returns whether there are no conditions, in which case any request satisfies them.
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    // returns these conditions along with the given ones.
+    pub fn and(&self, other: &Conditions) -> Conditions {
+        let mut conditions = self.clone();
+        conditions
+            .conditions
+            .extend(other.conditions.iter().cloned());
+        conditions
+    }
+
+    // checks whether the request satisfies the conditions, returning the status to respond with
+    // if it does not: 404 when its query is not the one required, otherwise 415 when its content
+    // type is not, and 406 otherwise. When `explicit` is set, the `Accept` header must list the
+    // media types required exactly rather than through a range or by being missing.
+    pub fn check(&self, req: &Request, explicit: bool) -> Result<(), StatusCode> {
+        if self.matched_query(req).is_none() {
+            return Err(StatusCode::NotFound);
+        }
+        let mut status = Ok(());
+        for c in &self.conditions {
+            match *c {
+                Condition::Query(..) => {}
+                Condition::ContentType(ref media_type) => {
+                    let content_type = header(req, "Content-Type").map(|v| essence(&v));
+                    match content_type {
+                        Some(ref ct) if media_matches(media_type, ct) => {}
+                        _ => return Err(StatusCode::UnsupportedMediaType),
+                    }
+                }
+                Condition::Accept(ref media_type) => {
+                    if !accepts(req, media_type, explicit) {
+                        status = Err(StatusCode::NotAcceptable);
+                    }
+                }
+                Condition::Header(ref name, ref value) => {
+                    let found = req.headers().get_raw(name).is_some_and(|raw| {
+                        raw.into_iter()
+                            .any(|line| str::from_utf8(line).map(str::trim) == Ok(value))
+                    });
+                    if !found {
+                        status = Err(StatusCode::NotAcceptable);
+                    }
+                }
+            }
+        }
+        status
+    }
+
+    // returns the conditions sorted and without repetitions, to compare them as a set.
+    fn set(&self) -> Vec<&Condition> {
+        let mut set: Vec<&Condition> = self.conditions.iter().collect();
+        set.sort();
+        set.dedup();
+        set
+    }
+
+    // returns the values of the query parameters required by the conditions, keyed by name, or
+    // `None` if the query of the request does not satisfy them.
+    pub fn matched_query(&self, req: &Request) -> Option<HashMap<String, String>> {
+        let mut values = HashMap::new();
+        let mut pairs = None;
+        for c in &self.conditions {
+            if let Condition::Query(ref name, ref required) = *c {
+                let pairs = pairs.get_or_insert_with(|| query_pairs(req.query().unwrap_or("")));
+                let value = pairs.iter().find(|p| p.0 == *name).map(|p| &p.1)?;
+                if required.as_ref().is_some_and(|r| r != value) {
+                    return None;
+                }
+                values.insert(name.clone(), value.clone());
+            }
+        }
+        Some(values)
+    }
+}
+
+impl PartialEq for Conditions {
+    fn eq(&self, other: &Conditions) -> bool {
+        self.set() == other.set()
+    }
+}
+
+impl fmt::Display for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, c) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match *c {
+                Condition::Accept(ref media_type) => write!(f, "Accept: {}", media_type)?,
+                Condition::ContentType(ref media_type) => {
+                    write!(f, "Content-Type: {}", media_type)?
+                }
+                Condition::Header(ref name, ref value) => write!(f, "{}: {}", name, value)?,
+                Condition::Query(ref name, Some(ref value)) => write!(f, "?{}={}", name, value)?,
+                Condition::Query(ref name, None) => write!(f, "?{}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// returns the name and value of each parameter of the query, percent-decoded and with `+` as a
+// space, in the order they appear.
+pub fn query_pairs(query: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| {
+        percent_decode(s.replace('+', " ").as_bytes())
+            .decode_utf8_lossy()
+            .into_owned()
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let name = decode(kv.next().unwrap_or(""));
+            (name, decode(kv.next().unwrap_or("")))
+        })
+        .collect()
+}
+
+// returns the media type without its parameters, in lowercase.
+fn essence(media_type: &str) -> String {
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+// returns whether the media range, eg. `*/*`, `text/*` or `text/html`, includes the media type.
+fn media_matches(range: &str, media_type: &str) -> bool {
+    if range == "*/*" || range == media_type {
+        return true;
+    }
+    match (range.strip_suffix("/*"), media_type.split('/').next()) {
+        (Some(r), Some(t)) => r == t,
+        _ => false,
+    }
+}
+
+// returns the values of the header joined by commas, if the request has it.
+fn header(req: &Request, name: &str) -> Option<String> {
+    let raw = req.headers().get_raw(name)?;
+    let values: Vec<&str> = raw
+        .into_iter()
+        .filter_map(|line| str::from_utf8(line).ok())
+        .collect();
+    Some(values.join(","))
+}
+
+// returns whether the `Accept` header of the request includes the media type with a non-zero
+// quality, or is missing. When `explicit` is set, the media type itself must be listed.
+fn accepts(req: &Request, media_type: &str, explicit: bool) -> bool {
+    let accept = match header(req, "Accept") {
+        Some(accept) => accept,
+        None => return !explicit,
+    };
+    accept.split(',').any(|range| {
+        let mut parts = range.split(';');
+        let range = essence(parts.next().unwrap_or(""));
+        let refused = parts.any(|p| {
+            let p = p.trim();
+            (p.starts_with("q=") || p.starts_with("Q="))
+                && p[2..].trim().parse::<f32>().is_ok_and(|q| q <= 0.0)
+        });
+        let matches = if explicit {
+            range == media_type
+        } else {
+            media_matches(&range, media_type)
+        };
+        !refused && matches
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::Method;
+
+    fn request(headers: &[(&'static str, &'static str)]) -> Request {
+        let mut req = Request::new(Method::Get, "/".parse().unwrap());
+        for &(name, value) in headers {
+            req.headers_mut().append_raw(name, value);
+        }
+        req
+    }
+
+    #[test]
+    fn accept() {
+        let c = Conditions::new().accept("application/vnd.api.v2+json");
+        let check = |accept: &'static str| c.check(&request(&[("Accept", accept)]), false);
+        assert_eq!(check("application/vnd.api.v2+json"), Ok(()));
+        assert_eq!(
+            check("text/html, Application/Vnd.Api.V2+JSON;q=0.5"),
+            Ok(())
+        );
+        assert_eq!(check("application/*"), Ok(()));
+        assert_eq!(check("*/*"), Ok(()));
+        assert_eq!(check("application/json"), Err(StatusCode::NotAcceptable));
+        assert_eq!(
+            check("application/vnd.api.v2+json;q=0"),
+            Err(StatusCode::NotAcceptable)
+        );
+        assert_eq!(c.check(&request(&[]), false), Ok(()));
+
+        // only the media type itself is accepted explicitly
+        let explicit = |headers: &[(&'static str, &'static str)]| c.check(&request(headers), true);
+        assert_eq!(
+            explicit(&[("Accept", "text/html, application/vnd.api.v2+json;q=0.5")]),
+            Ok(())
+        );
+        assert_eq!(explicit(&[("Accept", "application/*")]), Err(StatusCode::NotAcceptable));
+        assert_eq!(explicit(&[("Accept", "*/*")]), Err(StatusCode::NotAcceptable));
+        assert_eq!(explicit(&[]), Err(StatusCode::NotAcceptable));
+    }
+
+    #[test]
+    fn content_type() {
+        let c = Conditions::new().content_type("application/json");
+        let check = |ct: &'static str| c.check(&request(&[("Content-Type", ct)]), false);
+        assert_eq!(check("application/json"), Ok(()));
+        assert_eq!(check("application/json; charset=utf-8"), Ok(()));
+        assert_eq!(check("text/plain"), Err(StatusCode::UnsupportedMediaType));
+        assert_eq!(
+            c.check(&request(&[]), false),
+            Err(StatusCode::UnsupportedMediaType)
+        );
+
+        let c = Conditions::new().content_type("text/*");
+        let req = request(&[("Content-Type", "text/csv")]);
+        assert_eq!(c.check(&req, false), Ok(()));
+    }
+
+    #[test]
+    fn header() {
+        let c = Conditions::new().header("X-API-Version", "2");
+        assert_eq!(c.check(&request(&[("x-api-version", " 2 ")]), false), Ok(()));
+        assert_eq!(
+            c.check(&request(&[("X-API-Version", "1")]), false),
+            Err(StatusCode::NotAcceptable)
+        );
+        assert_eq!(c.check(&request(&[]), false), Err(StatusCode::NotAcceptable));
+    }
+
+    #[test]
+    fn query() {
+        let c = Conditions::new().query("type", "user").query_param("q");
+        let matched = |uri: &str| {
+            let req = Request::new(Method::Get, uri.parse().unwrap());
+            c.matched_query(&req).map(|q| {
+                let mut q: Vec<(String, String)> = q.into_iter().collect();
+                q.sort();
+                q
+            })
+        };
+        assert_eq!(
+            matched("/?q=a%20b&type=user&page=2"),
+            Some(vec![
+                ("q".to_owned(), "a b".to_owned()),
+                ("type".to_owned(), "user".to_owned()),
+            ])
+        );
+        assert_eq!(matched("/?q&type=user").map(|q| q.len()), Some(2));
+        assert_eq!(matched("/?q=a&type=repo"), None);
+        assert_eq!(matched("/?type=user"), None);
+        assert_eq!(matched("/"), None);
+        assert_eq!(c.to_string(), "?type=user, ?q");
+    }
+
+    #[test]
+    fn equality() {
+        let a = Conditions::new()
+            .accept("application/json")
+            .header("X-API-Version", "2");
+        let b = Conditions::new()
+            .header("x-api-version", "2")
+            .accept("Application/JSON")
+            .header("X-Api-Version", "2");
+        assert_eq!(a, b);
+        assert_ne!(a, b.and(&Conditions::new().header("X-API-Version", "3")));
+        assert_ne!(a, Conditions::new().accept("application/json"));
+        assert_ne!(a, a.and(&Conditions::new().query_param("q")));
+    }
+
+    #[test]
+    fn display() {
+        let c = Conditions::new()
+            .content_type("application/json; charset=utf-8")
+            .header("X-API-Version", "2");
+        assert_eq!(
+            c.to_string(),
+            "Content-Type: application/json, x-api-version: 2"
+        );
+    }
+}
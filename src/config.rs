@@ -0,0 +1,485 @@
+use futures::{future, Future};
+use hyper::header::{ContentLength, Location};
+use hyper::server::{Request, Response};
+use hyper::{Error, Method, StatusCode};
+use serde_json::{self, value::RawValue};
+use toml::{self, Spanned};
+
+use error::ConfigError;
+use middleware::Middleware;
+use node::{Handler, RequestData, RouteInfo};
+use RouteBuilder;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// the statuses a configured route may redirect with
+const REDIRECT_STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+
+/// ConfigFormat is the format of a config file describing routes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Returns the format of the file told by its extension, `.toml` or `.json`, if any.
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("toml") => Some(ConfigFormat::Toml),
+            Some(e) if e.eq_ignore_ascii_case("json") => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// HandlerRegistry holds the handlers and middleware, by name, the routes of a config file may
+/// refer to, see `RouteBuilder::load_config`.
+#[derive(Default)]
+pub struct HandlerRegistry {
+    handlers: HashMap<String, Arc<Box<Handler>>>,
+    middleware: HashMap<String, Arc<Box<Middleware + Send + Sync>>>,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        HandlerRegistry::default()
+    }
+
+    /// Registers the handler under the name, replacing any handler registered under it before.
+    pub fn handler<H>(mut self, name: &str, handler: H) -> Self
+    where
+        H: Sized + Handler,
+    {
+        self.handlers
+            .insert(name.to_owned(), Arc::new(Box::new(handler)));
+        self
+    }
+
+    /// Registers the middleware under the name, replacing any middleware registered under it
+    /// before.
+    pub fn middleware<M>(mut self, name: &str, middleware: M) -> Self
+    where
+        M: 'static + Send + Sync + Middleware,
+    {
+        self.middleware
+            .insert(name.to_owned(), Arc::new(Box::new(middleware)));
+        self
+    }
+}
+
+// reads the config file, returning its format told by its extension along with its contents.
+pub fn read(path: &Path) -> Result<(ConfigFormat, String), ConfigError> {
+    let format = ConfigFormat::from_path(path).ok_or_else(|| ConfigError::UnknownFormat {
+        file: path.display().to_string(),
+    })?;
+    let source = fs::read_to_string(path).map_err(|e| ConfigError::Io {
+        file: path.display().to_string(),
+        reason: e.to_string(),
+    })?;
+    Ok((format, source))
+}
+
+// RouteConfig is a route as described in a config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteConfig {
+    method: String,
+    path: String,
+    handler: Option<String>,
+    redirect: Option<String>,
+    status: Option<u16>,
+    #[serde(default)]
+    middleware: Vec<String>,
+    name: Option<String>,
+    #[serde(default = "enabled")]
+    enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlConfig {
+    routes: Vec<Spanned<RouteConfig>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonConfig<'a> {
+    #[serde(borrow)]
+    routes: Vec<&'a RawValue>,
+}
+
+// parses the routes described by the source, along with the line each starts at.
+fn parse(source: &str, format: ConfigFormat) -> Result<Vec<(usize, RouteConfig)>, ConfigError> {
+    match format {
+        ConfigFormat::Toml => {
+            let config: TomlConfig = toml::from_str(source).map_err(|e| ConfigError::Syntax {
+                line: e.line_col().map(|(line, _)| line + 1),
+                reason: reason(&e),
+            })?;
+            // toml gives no span for the tables of `[[routes]]`, their headers are found instead
+            let mut headers = source
+                .lines()
+                .enumerate()
+                .filter(|&(_, l)| is_routes_header(l))
+                .map(|(i, _)| i + 1);
+            Ok(config
+                .routes
+                .into_iter()
+                .map(|r| {
+                    let line = match r.span() {
+                        (0, 0) => headers.next().unwrap_or(1),
+                        (start, _) => line_of(source, start),
+                    };
+                    (line, r.into_inner())
+                })
+                .collect())
+        }
+        ConfigFormat::Json => {
+            let config: JsonConfig =
+                serde_json::from_str(source).map_err(|e| ConfigError::Syntax {
+                    line: Some(e.line()),
+                    reason: reason(&e),
+                })?;
+            let mut routes = Vec::new();
+            for raw in config.routes {
+                // the raw value borrows from the source, so its offset within it is known
+                let line = line_of(
+                    source,
+                    raw.get().as_ptr() as usize - source.as_ptr() as usize,
+                );
+                let route = serde_json::from_str(raw.get()).map_err(|e| ConfigError::Syntax {
+                    line: Some(line + e.line() - 1),
+                    reason: reason(&e),
+                })?;
+                routes.push((line, route));
+            }
+            Ok(routes)
+        }
+    }
+}
+
+// returns the line, starting from 1, the byte offset is at within the source.
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+// returns whether the line is a `[[routes]]` header, ignoring whitespace and comments.
+fn is_routes_header(line: &str) -> bool {
+    let line = line.split('#').next().unwrap_or("").trim();
+    line.starts_with("[[") && line.ends_with("]]") && line[2..line.len() - 2].trim() == "routes"
+}
+
+// returns the message of the parse error, without the position both TOML and JSON errors end
+// with since it is reported as the line of the `ConfigError`.
+fn reason<E: ::std::fmt::Display>(error: &E) -> String {
+    let mut reason = error.to_string();
+    if let Some(i) = reason.rfind(" at line ") {
+        reason.truncate(i);
+    }
+    reason
+}
+
+// registers the routes described by the source on the builder, resolving the names of their
+// handlers and middleware using the registry.
+pub fn load(
+    mut builder: RouteBuilder,
+    source: &str,
+    format: ConfigFormat,
+    registry: &HandlerRegistry,
+) -> Result<RouteBuilder, ConfigError> {
+    for (line, route) in parse(source, format)? {
+        let invalid = |reason: &str| ConfigError::InvalidRoute {
+            line,
+            reason: reason.to_owned(),
+        };
+
+        let method: Method = route
+            .method
+            .to_ascii_uppercase()
+            .parse()
+            .map_err(|_| invalid(&format!("invalid method '{}'", route.method)))?;
+        let handler = match (route.handler, route.redirect) {
+            (Some(name), None) => {
+                if route.status.is_some() {
+                    return Err(invalid("'status' only applies to redirects"));
+                }
+                match registry.handlers.get(&name) {
+                    Some(h) => Configured::Handler(h.clone()),
+                    None => return Err(ConfigError::UnknownHandler { line, name }),
+                }
+            }
+            (None, Some(location)) => {
+                let status = route.status.unwrap_or(301);
+                // other 3xx statuses, eg. 300 or 304, do not redirect to the location
+                match StatusCode::try_from(status) {
+                    Ok(s) if REDIRECT_STATUSES.contains(&status) => {
+                        Configured::Redirect(s, location)
+                    }
+                    _ => return Err(invalid(&format!("invalid redirect status {}", status))),
+                }
+            }
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "a route has either a 'handler' or a 'redirect', not both",
+                ))
+            }
+            (None, None) => {
+                return Err(invalid("a route needs either a 'handler' or a 'redirect'"))
+            }
+        };
+        let mut middleware: Vec<Box<Middleware>> = Vec::new();
+        for name in route.middleware {
+            match registry.middleware.get(&name) {
+                Some(m) => middleware.push(Box::new(Shared(m.clone()))),
+                None => return Err(ConfigError::UnknownMiddleware { line, name }),
+            }
+        }
+
+        // a disabled route is still checked, so that it can be enabled again safely
+        if !route.enabled {
+            continue;
+        }
+        let mut info = RouteInfo::new(method, &route.path, None);
+        info.name = route.name;
+        builder
+            .register(info, handler, Some(middleware), false)
+            .map_err(|error| ConfigError::Route { line, error })?;
+    }
+    Ok(builder)
+}
+
+// Configured is the handler of a route described in a config file.
+enum Configured {
+    Handler(Arc<Box<Handler>>),
+    Redirect(StatusCode, String),
+}
+
+impl Handler for Configured {
+    fn handle(
+        &self,
+        req: Request,
+        params: RequestData,
+    ) -> Box<Future<Item = Response, Error = Error>> {
+        match *self {
+            Configured::Handler(ref h) => h.handle(req, params),
+            Configured::Redirect(status, ref location) => Box::new(future::ok(
+                Response::new()
+                    .with_status(status)
+                    .with_header(Location::new(location.clone()))
+                    .with_header(ContentLength(0)),
+            )),
+        }
+    }
+}
+
+// Shared is a middleware of the registry, which may be used by several routes.
+struct Shared(Arc<Box<Middleware + Send + Sync>>);
+
+impl Middleware for Shared {
+    fn next(&self, handler: Box<Handler>) -> Box<Handler> {
+        self.0.next(handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use error::RouteError;
+    use futures::Stream;
+    use hyper::header::Location;
+    use std::str;
+
+    fn page(_req: Request, _data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+        Box::new(future::ok(Response::new().with_body("page")))
+    }
+
+    // appends `!` to the body of the response.
+    fn shout(handler: Box<Handler>) -> Box<Handler> {
+        Box::new(move |req: Request, data: RequestData| {
+            let res = handler.handle(req, data).and_then(|res| {
+                res.body().concat2().map(|body| {
+                    let body = format!("{}!", str::from_utf8(&body).unwrap());
+                    Response::new().with_body(body)
+                })
+            });
+            let res: Box<Future<Item = Response, Error = Error>> = Box::new(res);
+            res
+        })
+    }
+
+    fn registry() -> HandlerRegistry {
+        HandlerRegistry::new()
+            .handler("page", page)
+            .middleware("shout", shout)
+    }
+
+    fn load(source: &str, format: ConfigFormat) -> Result<RouteBuilder, ConfigError> {
+        RouteBuilder::new().parse_config(source, format, &registry())
+    }
+
+    // returns the status, location and body of the response to a GET request for the uri.
+    fn get(builder: RouteBuilder, uri: &str) -> (StatusCode, Option<String>, String) {
+        let router = builder.finalize();
+        let req = Request::new(Method::Get, uri.parse().unwrap());
+        let res = router
+            .handler
+            .handle(req, RequestData::default())
+            .wait()
+            .unwrap();
+        let status = res.status();
+        let location = res.headers().get::<Location>().map(|l| l.to_string());
+        let body = res.body().concat2().wait().unwrap();
+        (status, location, str::from_utf8(&body).unwrap().to_owned())
+    }
+
+    const TOML: &str = r#"
+[[routes]]
+method = "GET"
+path = "/users/:id"
+handler = "page"
+middleware = ["shout"]
+name = "user"
+
+[[routes]]
+method = "get"
+path = "/people/:id"
+redirect = "/users"
+status = 308
+
+[[routes]]
+method = "GET"
+path = "/beta"
+handler = "page"
+enabled = false
+"#;
+
+    const JSON: &str = r#"{
+    "routes": [
+        {
+            "method": "GET",
+            "path": "/users/:id",
+            "handler": "page",
+            "middleware": ["shout"],
+            "name": "user"
+        },
+        {"method": "get", "path": "/people/:id", "redirect": "/users", "status": 308},
+        {"method": "GET", "path": "/beta", "handler": "page", "enabled": false}
+    ]
+}"#;
+
+    #[test]
+    fn load_routes() {
+        for &(source, format) in &[(TOML, ConfigFormat::Toml), (JSON, ConfigFormat::Json)] {
+            let router = load(source, format).unwrap().finalize();
+            assert_eq!(
+                router.url_for("user", &[("id", "5")]),
+                Ok("/users/5".to_owned())
+            );
+
+            let res = get(load(source, format).unwrap(), "/users/5");
+            assert_eq!(res, (StatusCode::Ok, None, "page!".to_owned()));
+            let res = get(load(source, format).unwrap(), "/people/5");
+            assert_eq!(res.0, StatusCode::PermanentRedirect);
+            assert_eq!(res.1, Some("/users".to_owned()));
+            let res = get(load(source, format).unwrap(), "/beta");
+            assert_eq!(res.0, StatusCode::NotFound);
+        }
+    }
+
+    #[test]
+    fn error_lines() {
+        let toml = "[[routes]]\nmethod = \"GET\"\npath = \"/\"\nhandler = \"page\"\n\n\
+                    [[routes]]\nmethod = \"GET\"\npath = \"/x\"\nhandler = \"missing\"\n";
+        assert_eq!(
+            load(toml, ConfigFormat::Toml).err(),
+            Some(ConfigError::UnknownHandler {
+                line: 6,
+                name: "missing".to_owned(),
+            })
+        );
+
+        let toml = "routes = [\n  { method = \"GET\", path = \"/\", handler = \"page\" },\n  \
+                    { method = \"GET\", path = \"/x\", handler = \"missing\" },\n]\n";
+        assert_eq!(
+            load(toml, ConfigFormat::Toml).err().and_then(|e| e.line()),
+            Some(3)
+        );
+
+        let json =
+            "{\"routes\": [\n  {\"method\": \"GET\", \"path\": \"/\", \"handler\": \"page\"},\n  \
+                    {\"method\": \"GET\", \"path\": \"/:id/:id\", \"handler\": \"page\"}\n]}";
+        match load(json, ConfigFormat::Json) {
+            Err(ConfigError::Route {
+                line: 3,
+                error: RouteError::DuplicateParam { .. },
+            }) => {}
+            r => panic!("unexpected result {:?}", r.err()),
+        }
+
+        let json =
+            "{\"routes\": [\n  {\"method\": \"GET\", \"path\": \"/\", \"handler\": \"page\"},\n  \
+                    {\"method\": \"GET\",\n   \"paht\": \"/\"}\n]}";
+        let err = load(json, ConfigFormat::Json).err().unwrap();
+        assert_eq!(err.line(), Some(4));
+        assert!(
+            err.to_string().starts_with("line 4: unknown field `paht`"),
+            "{}",
+            err
+        );
+
+        let toml = "[[routes]]\nmethod = \"GET\"\npath = \"/\"\nhandler = \"page\"\nmiddleware = [\"loud\"]\n";
+        assert_eq!(
+            load(toml, ConfigFormat::Toml).err(),
+            Some(ConfigError::UnknownMiddleware {
+                line: 1,
+                name: "loud".to_owned(),
+            })
+        );
+
+        let toml = "[[routes]]\nmethod = \"GET\"\npath = \"/\"\nredirect = \"/a\"\nstatus = 200\n";
+        let err = load(toml, ConfigFormat::Toml).err().unwrap();
+        assert_eq!(err.to_string(), "line 1: invalid redirect status 200");
+        let toml = "[[routes]]\nmethod = \"GET\"\npath = \"/\"\nredirect = \"/a\"\nstatus = 304\n";
+        let err = load(toml, ConfigFormat::Toml).err().unwrap();
+        assert_eq!(err.to_string(), "line 1: invalid redirect status 304");
+
+        let err = load("[[routes]]\nmethod = ", ConfigFormat::Toml)
+            .err()
+            .unwrap();
+        assert_eq!(err.line(), Some(2));
+
+        // a source without routes, eg. a file being written, is rejected rather than loaded empty
+        for &(source, format) in &[("", ConfigFormat::Toml), ("{}", ConfigFormat::Json)] {
+            match load(source, format) {
+                Err(ConfigError::Syntax { ref reason, .. }) if reason.contains("routes") => {}
+                r => panic!("unexpected result {:?}", r.err()),
+            }
+        }
+        assert!(load("routes = []", ConfigFormat::Toml).is_ok());
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("routes.toml")),
+            Some(ConfigFormat::Toml)
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a/routes.JSON")),
+            Some(ConfigFormat::Json)
+        );
+        assert_eq!(ConfigFormat::from_path(Path::new("routes.yaml")), None);
+        assert!(RouteBuilder::new()
+            .load_config("routes.yaml", &registry())
+            .is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use hyper::Method;
+
+use std::error;
+use std::fmt;
+use std::panic::Location;
+
+/// RouteError is returned when a route cannot be registered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteError {
+    /// The pattern does not start with a `/`.
+    MissingLeadingSlash { pattern: String },
+    /// A segment of the pattern could not be parsed, eg. an invalid constraint `:id(\d+[)`.
+    InvalidSegment {
+        pattern: String,
+        segment: String,
+        reason: String,
+    },
+    /// The same parameter name is used more than once within the pattern, eg. `/:id/:id`.
+    DuplicateParam { pattern: String, param: String },
+    /// A parameter or wildcard conflicts with the one registered at the same position by
+    /// another route, eg. `/user/:foo` and `/user/:bar/profile`.
+    Conflict {
+        pattern: String,
+        segment: String,
+        existing: String,
+        route: String,
+    },
+    /// A route was already registered for the same method and path, eg. `/user/:id` twice.
+    ///
+    /// The locations are those of the calls which registered each route, when known.
+    Duplicate {
+        method: Method,
+        pattern: String,
+        location: Option<&'static Location<'static>>,
+        route: String,
+        route_location: Option<&'static Location<'static>>,
+    },
+    /// A host pattern could not be parsed, eg. `api.*.example.com`.
+    InvalidHost { host: String, reason: String },
+    /// The host pattern matches the same hosts as another one registered, while naming its
+    /// parameters differently, eg. `:tenant.example.com` and `:org.example.com`.
+    ConflictingHost { host: String, existing: String },
+    /// The name was already given to a route with a differing pattern.
+    DuplicateName {
+        name: String,
+        pattern: String,
+        route: String,
+    },
+}
+
+impl RouteError {
+    /// Returns the pattern of the route which could not be registered, or that of the host.
+    pub fn pattern(&self) -> &str {
+        match *self {
+            RouteError::MissingLeadingSlash { ref pattern }
+            | RouteError::InvalidSegment { ref pattern, .. }
+            | RouteError::DuplicateParam { ref pattern, .. }
+            | RouteError::Conflict { ref pattern, .. }
+            | RouteError::Duplicate { ref pattern, .. }
+            | RouteError::DuplicateName { ref pattern, .. }
+            | RouteError::InvalidHost {
+                host: ref pattern, ..
+            }
+            | RouteError::ConflictingHost {
+                host: ref pattern, ..
+            } => pattern,
+        }
+    }
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RouteError::MissingLeadingSlash { ref pattern } => {
+                write!(f, "paths must start with '/', found '{}'", pattern)
+            }
+            RouteError::InvalidSegment {
+                ref pattern,
+                ref segment,
+                ref reason,
+            } => write!(
+                f,
+                "invalid segment '{}' for path '{}': {}",
+                segment, pattern, reason
+            ),
+            RouteError::DuplicateParam {
+                ref pattern,
+                ref param,
+            } => write!(
+                f,
+                "conflicting parameter names detected for path {}, for parameter {}",
+                pattern, param
+            ),
+            RouteError::Conflict {
+                ref pattern,
+                ref segment,
+                ref existing,
+                ref route,
+            } => write!(
+                f,
+                "conflicting id's detected '{}' and '{}' for path '{}', conflicts with route '{}'",
+                existing, segment, pattern, route
+            ),
+            RouteError::Duplicate {
+                ref method,
+                ref pattern,
+                ref location,
+                ref route,
+                ref route_location,
+            } => {
+                write!(f, "duplicate route detected {} '{}'", method, pattern)?;
+                if let Some(l) = *location {
+                    write!(f, " at {}", l)?;
+                }
+                write!(f, ", already registered as '{}'", route)?;
+                if let Some(l) = *route_location {
+                    write!(f, " at {}", l)?;
+                }
+                Ok(())
+            }
+            RouteError::InvalidHost {
+                ref host,
+                ref reason,
+            } => write!(f, "invalid host '{}': {}", host, reason),
+            RouteError::ConflictingHost {
+                ref host,
+                ref existing,
+            } => write!(
+                f,
+                "conflicting parameter names detected for host '{}', conflicts with host '{}'",
+                host, existing
+            ),
+            RouteError::DuplicateName {
+                ref name,
+                ref pattern,
+                ref route,
+            } => write!(
+                f,
+                "duplicate route name '{}' for path '{}', already given to route '{}'",
+                name, pattern, route
+            ),
+        }
+    }
+}
+
+impl error::Error for RouteError {}
+
+/// UrlError is returned when the url of a named route cannot be built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UrlError {
+    /// No route was registered with the name.
+    UnknownRoute(String),
+    /// No value was given for a parameter of the route, which is not optional.
+    MissingParam { route: String, param: String },
+    /// A value was given for a parameter the route does not have.
+    UnknownParam { route: String, param: String },
+    /// The value is not accepted by the parameter, either being empty or not satisfying its
+    /// constraint.
+    InvalidParam {
+        route: String,
+        param: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UrlError::UnknownRoute(ref name) => write!(f, "no route named '{}'", name),
+            UrlError::MissingParam {
+                ref route,
+                ref param,
+            } => write!(f, "parameter '{}' missing for route '{}'", param, route),
+            UrlError::UnknownParam {
+                ref route,
+                ref param,
+            } => write!(f, "route '{}' has no parameter '{}'", route, param),
+            UrlError::InvalidParam {
+                ref route,
+                ref param,
+                ref value,
+            } => write!(
+                f,
+                "value '{}' is invalid for parameter '{}' of route '{}'",
+                value, param, route
+            ),
+        }
+    }
+}
+
+impl error::Error for UrlError {}
+
+/// ConfigError is returned when routes cannot be loaded from a config file, along with the line
+/// of the file the error was found at, starting from 1, when known.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The file could not be read.
+    Io { file: String, reason: String },
+    /// The format of the file could not be told from its extension, `.toml` or `.json`.
+    UnknownFormat { file: String },
+    /// The file is not valid TOML or JSON, or does not describe routes as expected.
+    Syntax { line: Option<usize>, reason: String },
+    /// No handler was registered with the name.
+    UnknownHandler { line: usize, name: String },
+    /// No middleware was registered with the name.
+    UnknownMiddleware { line: usize, name: String },
+    /// The route is inconsistent, eg. it has both a handler and a redirect, or an invalid method.
+    InvalidRoute { line: usize, reason: String },
+    /// The route could not be registered.
+    Route { line: usize, error: RouteError },
+    /// The routes, considered as a whole, are invalid.
+    Routes(RouteError),
+}
+
+impl ConfigError {
+    /// Returns the line of the file the error was found at, when known.
+    pub fn line(&self) -> Option<usize> {
+        match *self {
+            ConfigError::Io { .. } | ConfigError::UnknownFormat { .. } | ConfigError::Routes(_) => {
+                None
+            }
+            ConfigError::Syntax { line, .. } => line,
+            ConfigError::UnknownHandler { line, .. }
+            | ConfigError::UnknownMiddleware { line, .. }
+            | ConfigError::InvalidRoute { line, .. }
+            | ConfigError::Route { line, .. } => Some(line),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(line) = self.line() {
+            write!(f, "line {}: ", line)?;
+        }
+        match *self {
+            ConfigError::Io {
+                ref file,
+                ref reason,
+            } => write!(f, "cannot read '{}': {}", file, reason),
+            ConfigError::UnknownFormat { ref file } => write!(
+                f,
+                "unknown format for '{}', expected a '.toml' or '.json' file",
+                file
+            ),
+            ConfigError::Syntax { ref reason, .. } => write!(f, "{}", reason),
+            ConfigError::UnknownHandler { ref name, .. } => {
+                write!(f, "no handler registered as '{}'", name)
+            }
+            ConfigError::UnknownMiddleware { ref name, .. } => {
+                write!(f, "no middleware registered as '{}'", name)
+            }
+            ConfigError::InvalidRoute { ref reason, .. } => write!(f, "{}", reason),
+            ConfigError::Route { ref error, .. } | ConfigError::Routes(ref error) => {
+                write!(f, "{}", error)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
@@ -0,0 +1,119 @@
+use hyper::{Method, Uri};
+
+use host;
+use node::{Outcome, RouteInfo, Step};
+use router::RouteTable;
+
+use std::fmt;
+
+/// Explanation describes how a request would be routed, step by step, see `Router::explain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub method: Method,
+    pub path: String,
+    /// The search within the routes of each host matching the request, from the most specific
+    /// host to the routes registered without a host, stopping at the first to find a route.
+    pub searches: Vec<Search>,
+    /// The route which would handle the request, if any.
+    pub route: Option<RouteInfo>,
+    /// The other methods having a route matching the path; when no route was found they are the
+    /// methods a `405 Method Not Allowed` response would list as allowed.
+    pub allowed: Vec<Method>,
+}
+
+/// Search is the search for a route within the routes of a host, or those registered without a
+/// host, listing each child node tried in turn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Search {
+    /// The host pattern the routes were registered for, if any.
+    pub host: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+// explains how a request for the method and uri would be routed using the table; the uri being
+// either a path or an absolute uri, in which case the routes of its host are searched too.
+pub fn explain(table: &RouteTable, method: Method, uri: &str) -> Explanation {
+    let (host, path) = match uri.parse::<Uri>() {
+        Ok(u) => (u.host().and_then(host::normalize), u.path().to_owned()),
+        Err(_) => (None, uri.to_owned()),
+    };
+    let relative = path.get(1..).unwrap_or("");
+
+    let mut explanation = Explanation {
+        method: method.clone(),
+        path: path.clone(),
+        searches: Vec::new(),
+        route: None,
+        allowed: Vec::new(),
+    };
+    let routes = table.routes_for(host.as_deref());
+
+    for &(host, tree, _) in &routes {
+        let mut search = Search {
+            host: host.map(str::to_owned),
+            steps: Vec::new(),
+        };
+        let m = tree
+            .get(&method)
+            .and_then(|node| node.trace(relative, &mut search.steps));
+        explanation.searches.push(search);
+        if let Some(m) = m {
+            explanation.route = Some(m.info.clone());
+            break;
+        }
+    }
+
+    for &(_, tree, _) in &routes {
+        for (m, node) in tree {
+            if *m == method || explanation.allowed.contains(m) {
+                continue;
+            }
+            if node.find(relative).is_some() {
+                explanation.allowed.push(m.clone());
+            }
+        }
+    }
+    explanation.allowed.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    explanation
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", self.method, self.path)?;
+        for search in &self.searches {
+            match search.host {
+                Some(ref host) => writeln!(f, "routes of host '{}':", host)?,
+                None => writeln!(f, "routes without a host:")?,
+            }
+            if search.steps.is_empty() {
+                writeln!(f, "  no route registered for {}", self.method)?;
+            }
+            for step in &search.steps {
+                write!(
+                    f,
+                    "  {}{} '{}' ",
+                    "  ".repeat(step.depth),
+                    step.kind.as_str(),
+                    step.segment
+                )?;
+                match step.outcome {
+                    Outcome::Matched => writeln!(f, "matched '{}'", step.value)?,
+                    Outcome::Rejected => writeln!(f, "rejected '{}'", step.value)?,
+                    Outcome::NoRoute => {
+                        writeln!(f, "matched '{}', but has no route", step.value)?
+                    }
+                    Outcome::Found => writeln!(f, "matched '{}', route found", step.value)?,
+                }
+            }
+        }
+        match self.route {
+            Some(ref route) => writeln!(f, "handled by {} {}", route.method, route.pattern)?,
+            None => writeln!(f, "no route found")?,
+        }
+        if !self.allowed.is_empty() {
+            let allowed: Vec<&str> = self.allowed.iter().map(|m| m.as_ref()).collect();
+            writeln!(f, "other methods matching: {}", allowed.join(", "))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use serde_json::{self, Value};
+
+use node::{Node, NodeKind};
+use router::{RouteTable, Routes};
+
+use std::fmt::Write;
+
+// Merged is a node of the trees of every method merged together, along with the methods having
+// a route registered on it.
+struct Merged<'a> {
+    kind: Option<NodeKind>,
+    segment: &'a str,
+    methods: Vec<&'a str>,
+    children: Vec<Merged<'a>>,
+}
+
+impl<'a> Merged<'a> {
+    fn new(kind: Option<NodeKind>, segment: &'a str) -> Merged<'a> {
+        Merged {
+            kind,
+            segment,
+            methods: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    // merges the trees of every method into a single tree, the root having no kind.
+    fn from_routes(routes: &'a Routes) -> Merged<'a> {
+        let mut methods: Vec<_> = routes.iter().collect();
+        methods.sort_by_key(|m| m.0.as_ref());
+
+        let mut merged = Merged::new(None, "/");
+        for (method, node) in methods {
+            merged.merge(method.as_ref(), node);
+        }
+        merged.sort();
+        merged
+    }
+
+    fn merge(&mut self, method: &'a str, node: &'a Node) {
+        if node.route().is_some() {
+            self.methods.push(method);
+        }
+        for (kind, segment, child) in node.children() {
+            let index = match self
+                .children
+                .iter()
+                .position(|c| c.kind == Some(kind) && c.segment == segment)
+            {
+                Some(i) => i,
+                None => {
+                    self.children.push(Merged::new(Some(kind), segment));
+                    self.children.len() - 1
+                }
+            };
+            self.children[index].merge(method, child);
+        }
+    }
+
+    // orders the children as they are tried, statics by segment and the others in the order they
+    // were first registered.
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| {
+            a.kind.cmp(&b.kind).then_with(|| match a.kind {
+                Some(NodeKind::Static) => a.segment.cmp(b.segment),
+                _ => ::std::cmp::Ordering::Equal,
+            })
+        });
+        for child in &mut self.children {
+            child.sort();
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "segment": self.segment,
+            "kind": self.kind.map_or("root", |k| k.as_str()),
+            "methods": self.methods,
+            "children": self.children.iter().map(Merged::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    // writes this node and its children as DOT statements, returning the id of this node. The
+    // label of the root is prefixed by its host, if any.
+    fn to_dot(&self, host: Option<&str>, out: &mut String, next: &mut usize) -> usize {
+        let id = *next;
+        *next += 1;
+
+        let mut label = escape(host.unwrap_or(""));
+        label.push_str(&escape(self.segment));
+        if !self.methods.is_empty() {
+            label.push_str(&format!("\\n[{}]", self.methods.join(", ")));
+        }
+        let style = match self.kind {
+            Some(NodeKind::Param) | Some(NodeKind::Pattern) => ", style=dashed",
+            Some(NodeKind::Wildcard) => ", style=dotted",
+            _ => "",
+        };
+        let _ = writeln!(out, "    n{} [label=\"{}\"{}];", id, label, style);
+
+        for child in &self.children {
+            let child_id = child.to_dot(None, out, next);
+            let _ = writeln!(out, "    n{} -> n{};", id, child_id);
+        }
+        id
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// returns the merged tree of the routes registered without a host, followed by that of each host.
+fn roots(table: &RouteTable) -> Vec<(Option<&str>, Merged<'_>)> {
+    let mut roots = vec![(None, Merged::from_routes(&table.routes))];
+    for (host, routes) in &table.hosts {
+        roots.push((Some(host.as_str()), Merged::from_routes(routes)));
+    }
+    roots
+}
+
+/// Returns the routing tree as a Graphviz DOT digraph, with a node for each segment labelled with
+/// the methods having a route registered on it. Params and patterns are dashed, wildcards dotted.
+pub fn to_dot(table: &RouteTable) -> String {
+    let mut out = String::from("digraph routes {\n    node [shape=box];\n");
+    let mut next = 0;
+    for (host, root) in roots(table) {
+        root.to_dot(host, &mut out, &mut next);
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Returns the routing tree as JSON, each node listing its segment, kind, the methods having a
+/// route registered on it and its children in the order they are tried.
+pub fn to_json(table: &RouteTable) -> String {
+    let mut roots = roots(table).into_iter();
+    let routes = roots.next().map(|(_, r)| r.to_json());
+    let hosts: Vec<Value> = roots
+        .map(|(host, r)| {
+            json!({
+                "host": host,
+                "routes": r.to_json(),
+            })
+        })
+        .collect();
+    let tree = json!({
+        "routes": routes,
+        "hosts": hosts,
+    });
+    serde_json::to_string_pretty(&tree).unwrap_or_default()
+}
@@ -0,0 +1,258 @@
+use hyper::header::Host;
+use hyper::server::Request;
+
+use error::RouteError;
+
+/// HostPattern matches the host a request was sent to, label by label, eg. `api.example.com`.
+///
+/// A label may be a parameter capturing exactly one label of the host eg. `:tenant.example.com`,
+/// and the first label may be a wildcard `*` matching one or more labels eg. `*.example.com`.
+/// Static labels are compared regardless of ASCII case.
+#[derive(Debug)]
+pub struct HostPattern {
+    pattern: String,
+    labels: Vec<Label>,
+    wild: bool,
+}
+
+#[derive(Debug)]
+enum Label {
+    Static(String),
+    Param(String),
+}
+
+impl HostPattern {
+    /// Parses the host pattern, returning an error if a label is empty, a parameter has no name
+    /// or a wildcard is not the first label. Static labels are lowercased, so that patterns
+    /// differing only by their case are the same.
+    pub fn parse(pattern: &str) -> Result<HostPattern, RouteError> {
+        let invalid = |reason: &str| RouteError::InvalidHost {
+            host: pattern.to_owned(),
+            reason: reason.to_owned(),
+        };
+
+        let mut labels = Vec::new();
+        let mut wild = false;
+        for (i, label) in pattern.split('.').enumerate() {
+            if label.is_empty() {
+                return Err(invalid("empty label"));
+            }
+            if label == "*" {
+                if i > 0 {
+                    return Err(invalid("a wildcard '*' is only permitted as the first label"));
+                }
+                wild = true;
+                continue;
+            }
+            if label.contains('*') {
+                return Err(invalid("a wildcard '*' must be a label by itself"));
+            }
+            match label.strip_prefix(':') {
+                Some(name) => {
+                    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                        return Err(invalid("invalid parameter name"));
+                    }
+                    labels.push(Label::Param(label.to_owned()));
+                }
+                None => labels.push(Label::Static(label.to_ascii_lowercase())),
+            }
+        }
+        if wild && labels.is_empty() {
+            return Err(invalid("a wildcard '*' must be followed by another label"));
+        }
+
+        let normalized: Vec<String> = pattern
+            .split('.')
+            .map(|label| {
+                if label.starts_with(':') {
+                    label.to_owned()
+                } else {
+                    label.to_ascii_lowercase()
+                }
+            })
+            .collect();
+        Ok(HostPattern {
+            pattern: normalized.join("."),
+            labels,
+            wild,
+        })
+    }
+
+    /// Returns the pattern as it was registered, with its static labels in lowercase.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns how specific the pattern is, lower being more specific: exact hosts, then hosts
+    /// with parameters, then wildcard hosts.
+    pub fn rank(&self) -> u8 {
+        if self.wild {
+            return 2;
+        }
+        if self.labels.iter().any(|l| match *l {
+            Label::Param(_) => true,
+            Label::Static(_) => false,
+        }) {
+            return 1;
+        }
+        0
+    }
+
+    /// Returns the names of the parameters, including their leading `:`.
+    pub fn params(&self) -> Vec<&str> {
+        self.labels
+            .iter()
+            .filter_map(|l| match *l {
+                Label::Param(ref id) => Some(id.as_str()),
+                Label::Static(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns whether the pattern matches exactly the same hosts as the other one, while naming
+    /// its parameters differently, eg. `:tenant.example.com` and `:org.example.com`.
+    pub fn conflicts(&self, other: &HostPattern) -> bool {
+        if self.wild != other.wild || self.labels.len() != other.labels.len() {
+            return false;
+        }
+        let mut renamed = false;
+        for (label, other) in self.labels.iter().zip(&other.labels) {
+            match (label, other) {
+                (Label::Static(a), Label::Static(b)) if a == b => {}
+                (Label::Param(a), Label::Param(b)) => renamed |= a != b,
+                _ => return false,
+            }
+        }
+        renamed
+    }
+
+    /// Matches the host, which must already be in lowercase, returning the captured parameters.
+    pub fn matches<'a, 'b>(&'a self, host: &'b str) -> Option<Vec<(&'a str, &'b str)>> {
+        let labels: Vec<&str> = host.split('.').collect();
+        if labels.len() < self.labels.len() || (!self.wild && labels.len() > self.labels.len()) {
+            return None;
+        }
+        if self.wild && labels.len() == self.labels.len() {
+            return None;
+        }
+
+        // a wildcard covers the leading labels, so the pattern is compared to the trailing ones
+        let mut params = Vec::new();
+        let skip = labels.len() - self.labels.len();
+        for (label, value) in self.labels.iter().zip(&labels[skip..]) {
+            match *label {
+                Label::Static(ref s) => {
+                    if s != value {
+                        return None;
+                    }
+                }
+                Label::Param(ref id) => {
+                    if value.is_empty() {
+                        return None;
+                    }
+                    params.push((id.as_str(), *value));
+                }
+            }
+        }
+        Some(params)
+    }
+}
+
+// returns the host the request was sent to, in lowercase and without its port nor trailing dot;
+// taken from the URI when in absolute form, otherwise from the `Host` header.
+pub fn request_host(req: &Request) -> Option<String> {
+    match req.uri().host() {
+        Some(h) => normalize(h),
+        None => normalize(req.headers().get::<Host>()?.hostname()),
+    }
+}
+
+// returns the host in lowercase and without its trailing dot, or `None` if it's empty.
+pub fn normalize(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.');
+    if host.is_empty() {
+        return None;
+    }
+    Some(host.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, host: &str) -> Option<Vec<(String, String)>> {
+        HostPattern::parse(pattern).unwrap().matches(host).map(|params| {
+            params
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn exact_host() {
+        assert_eq!(matches("api.example.com", "api.example.com"), Some(vec![]));
+        assert_eq!(matches("API.Example.com", "api.example.com"), Some(vec![]));
+        assert_eq!(matches("api.example.com", "www.example.com"), None);
+        assert_eq!(matches("api.example.com", "example.com"), None);
+        assert_eq!(matches("api.example.com", "v1.api.example.com"), None);
+    }
+
+    #[test]
+    fn wildcard_host() {
+        assert_eq!(matches("*.example.com", "api.example.com"), Some(vec![]));
+        assert_eq!(matches("*.example.com", "v1.api.example.com"), Some(vec![]));
+        assert_eq!(matches("*.example.com", "example.com"), None);
+        assert_eq!(matches("*.example.com", "example.org"), None);
+    }
+
+    #[test]
+    fn param_host() {
+        assert_eq!(
+            matches(":tenant.example.com", "acme.example.com"),
+            Some(vec![(":tenant".to_owned(), "acme".to_owned())])
+        );
+        assert_eq!(matches(":tenant.example.com", "a.b.example.com"), None);
+        assert_eq!(
+            matches("*.:region.example.com", "api.eu.example.com"),
+            Some(vec![(":region".to_owned(), "eu".to_owned())])
+        );
+    }
+
+    #[test]
+    fn lowercase() {
+        let pattern = HostPattern::parse("*.:Tenant.API.Example.com").unwrap();
+        assert_eq!(pattern.as_str(), "*.:Tenant.api.example.com");
+        assert_eq!(pattern.params(), vec![":Tenant"]);
+    }
+
+    #[test]
+    fn rank() {
+        let rank = |p: &str| HostPattern::parse(p).unwrap().rank();
+        assert_eq!(rank("api.example.com"), 0);
+        assert_eq!(rank(":tenant.example.com"), 1);
+        assert_eq!(rank("*.example.com"), 2);
+    }
+
+    #[test]
+    fn conflicts() {
+        let conflicts = |a: &str, b: &str| {
+            HostPattern::parse(a)
+                .unwrap()
+                .conflicts(&HostPattern::parse(b).unwrap())
+        };
+        assert!(conflicts(":tenant.example.com", ":org.example.com"));
+        assert!(conflicts("*.:region.example.com", "*.:zone.example.com"));
+        assert!(!conflicts(":tenant.example.com", ":tenant.example.com"));
+        assert!(!conflicts(":tenant.example.com", ":org.example.org"));
+        assert!(!conflicts(":tenant.example.com", "*.:org.example.com"));
+        assert!(!conflicts(":tenant.example.com", "api.example.com"));
+    }
+
+    #[test]
+    fn invalid_host() {
+        for p in &["", "api..com", "api.*.com", "*", "*api.example.com", ":.example.com", ":a-b.com"] {
+            assert!(HostPattern::parse(p).is_err(), "parsing {}", p);
+        }
+    }
+}
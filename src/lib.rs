@@ -1,28 +1,75 @@
+// the fallible methods of the builder return it along with their error, so that it may be used
+// further once the error is reported
+#![allow(clippy::result_large_err)]
+
 extern crate futures;
 extern crate hyper;
-
+#[macro_use]
+extern crate percent_encoding;
+extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate toml;
+
+mod conditions;
+mod config;
+mod error;
+mod explain;
+mod export;
+mod host;
 mod middleware;
 mod node;
+mod options;
+mod param;
 mod router;
+mod watch;
 
-use hyper::header::{Allow, ContentLength};
+use hyper::header::{Allow, ContentLength, ContentType, Location as LocationHeader};
 use hyper::server::{Request, Response};
 use hyper::{Error, Method, StatusCode};
 
 use futures::Future;
 
-use node::Node;
-use router::{Router, Routes};
+use host::HostPattern;
+use node::{Match, Node, Route};
+use router::{HostRoutes, RouteTable, Routes};
 
 use std::collections::HashMap;
-
+use std::panic::Location;
+use std::path::Path;
+use std::sync::Arc;
+
+pub use conditions::Conditions;
+pub use config::{ConfigFormat, HandlerRegistry};
+pub use error::{ConfigError, RouteError, UrlError};
+pub use explain::{Explanation, Search};
 pub use middleware::Middleware;
-pub use node::{Handler, RequestData};
+pub use node::{Handler, NodeKind, Outcome, RequestData, RouteInfo, Step};
+pub use options::{CaseSensitivity, CleanPath, TrailingSlash};
+pub use param::ParamError;
+pub use router::{Router, SwappableRouter};
+pub use watch::{ConfigWatcher, Watch};
 
 pub struct RouteBuilder {
     tree: Routes,
+    hosts: Vec<(HostPattern, Routes)>,
+    // the index within `hosts` routes are currently being registered for, if any
+    host: Option<usize>,
+    // the groups routes are currently being registered within, outermost first
+    groups: Vec<Group>,
+    // the conditions of the routes currently being registered
+    conditions: Conditions,
+    // the pattern of each named route, by name
+    names: HashMap<String, String>,
     not_found: Box<node::Handler>,
     middleware: Option<Vec<Box<Middleware>>>,
+    trailing_slash: TrailingSlash,
+    clean_path: CleanPath,
+    case_sensitivity: CaseSensitivity,
+    explain_path: Option<String>,
 }
 
 impl RouteBuilder {
@@ -61,38 +108,60 @@ impl RouteBuilder {
     ///
     /// # Panics
     ///
-    /// - If two similar routes are configured with differing parameter names eg. `/user/:foo` and `/user/:bar/profile`
+    /// Registering a route panics in the following cases, use the `try_add` family of methods to
+    /// receive a `RouteError` instead.
+    ///
+    /// - If the path or the prefix of a group or mount does not start with a `/` eg. `user`
+    /// - If two similar routes are configured with differing parameter names eg. `/user/:foo` and `/user/:bar/profile`,
+    ///   unless the parameters carry differing constraints eg. `/user/:id(\d+)` and `/user/:name([a-z]+)`
+    /// - If a parameter constraint is not a valid regular expression eg. `/user/:id(\d+[)`
+    /// - If a parameter type is not one of `u64`, `i64`, `uuid`, `bool` or `str` eg. `/user/:id<u32>`
+    /// - If a required segment follows an optional parameter eg. `/archive/:year?/posts`
+    /// - If two parameters within a segment are not separated by static text eg. `/files/:name:ext`
     /// - If registering a duplicate wildcard route with differing wildcard names eg. `/user/*foo` and `/user/*bar`
-    /// - If a parameter or wildcard is configured after a wildcard eg. `/user/*/:foo`
+    /// - If the same method and path are registered twice, use `replace` to intentionally override a route
+    /// - If the same name is given to routes with differing paths
+    /// - If a parameter or wildcard is configured after a wildcard eg. `/user/*/:foo`, static segments
+    ///   are permitted eg. `/repos/*path/blob`
+    /// - If a host pattern is invalid eg. `api.*.example.com`
+    /// - If two host patterns differ only by the names of their parameters eg. `:tenant.example.com`
+    ///   and `:org.example.com`, which is reported when finalizing
     ///
     pub fn new() -> Self {
         RouteBuilder {
             tree: Routes::new(),
+            hosts: Vec::new(),
+            host: None,
+            groups: Vec::new(),
+            conditions: Conditions::new(),
+            names: HashMap::new(),
             not_found: Box::new(not_found),
             middleware: None,
+            trailing_slash: TrailingSlash::Strict,
+            clean_path: CleanPath::Off,
+            case_sensitivity: CaseSensitivity::Sensitive,
+            explain_path: None,
         }
     }
 
+    /// Adds the middleware, wrapping every route; or, when called within `RouteBuilder::group`,
+    /// only the routes of the group.
     pub fn with_middleware<MW>(mut self, middleware: MW) -> Self
     where
         MW: Sized + Middleware + 'static,
     {
-        if self.middleware.is_none() {
-            self.middleware = Some(Vec::new());
-        }
-        self.middleware.as_mut().unwrap().push(Box::new(middleware));
+        self.middleware_mut().push(Box::new(middleware));
         self
     }
 
+    /// Adds the middlewares, wrapping every route; or, when called within `RouteBuilder::group`,
+    /// only the routes of the group.
     pub fn with_middlewares<MW>(mut self, middleware: Vec<MW>) -> Self
     where
         MW: Sized + Middleware + 'static,
     {
-        if self.middleware.is_none() {
-            self.middleware = Some(Vec::new());
-        }
         {
-            let mw = self.middleware.as_mut().unwrap();
+            let mw = self.middleware_mut();
             for m in middleware {
                 mw.push(Box::new(m));
             }
@@ -100,6 +169,14 @@ impl RouteBuilder {
         self
     }
 
+    // returns the middleware of the innermost group being registered, or the global middleware.
+    fn middleware_mut(&mut self) -> &mut Vec<Box<Middleware>> {
+        match self.groups.last_mut() {
+            Some(group) => &mut group.middleware,
+            None => self.middleware.get_or_insert_with(Vec::new),
+        }
+    }
+
     pub fn set_not_found<H>(mut self, handler: H) -> Self
     where
         H: Sized + node::Handler,
@@ -108,159 +185,889 @@ impl RouteBuilder {
         self
     }
 
-    pub fn add_with_middleware<H>(
-        mut self,
-        method: Method,
-        path: &str,
-        handler: H,
-        middleware: Option<Vec<Box<Middleware>>>,
-    ) -> Self
-    where
-        H: Sized + node::Handler,
-    {
-        let (left, right) = path.split_at(1);
-
-        if left != "/" {
-            panic!("paths must start with '/'");
-        }
-
-        let mut params: HashMap<&str, bool> = HashMap::new();
-        for p in path.split("/").collect::<Vec<&str>>() {
-            if p.chars().nth(0) == Some(':') {
-                if params.get(p).is_some() {
-                    panic!(
-                        "conflicting parameter names detected for path {}, for paramter {}",
-                        path, p
-                    )
-                }
-                params.insert(p, true);
-            }
-        }
-
-        let mut h: Box<node::Handler> = Box::new(handler);
-
-        // middleware just for this handler
-        if middleware.is_some() {
-            let mw = middleware.as_ref().unwrap();
-            for i in (0..mw.len()).rev() {
-                h = mw[i].next(h);
-            }
-        }
+    /// Sets how requests differing from a route only by a trailing slash are handled, by default
+    /// they are not found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder, TrailingSlash};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// // `/users/` is redirected to `/users`
+    /// let router = RouteBuilder::new()
+    ///     .set_trailing_slash(TrailingSlash::Redirect(StatusCode::PermanentRedirect))
+    ///     .get("/users", users)
+    ///     .finalize();
+    ///
+    /// fn users(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("users"),
+    ///    ))
+    /// }
+    /// ```
+    pub fn set_trailing_slash(mut self, trailing_slash: TrailingSlash) -> Self {
+        self.trailing_slash = trailing_slash;
+        self
+    }
 
-        self.tree.entry(method).or_insert(Node::new()).add(right, h);
+    /// Sets whether request paths are cleaned before routing, by default they are routed as
+    /// requested.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{CleanPath, RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// // `//users/./5` and `/users/x/../5` are both routed as `/users/5`
+    /// let router = RouteBuilder::new()
+    ///     .set_clean_path(CleanPath::Route)
+    ///     .get("/users/:id", user)
+    ///     .finalize();
+    ///
+    /// fn user(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("user"),
+    ///    ))
+    /// }
+    /// ```
+    pub fn set_clean_path(mut self, clean_path: CleanPath) -> Self {
+        self.clean_path = clean_path;
         self
     }
 
-    pub fn add<H>(self, method: Method, path: &str, handler: H) -> Self
-    where
-        H: Sized + node::Handler,
-    {
-        self.add_with_middleware(method, path, handler, None)
+    /// Sets whether static segments of request paths must match the case of the routes, by default
+    /// they must. See `CaseSensitivity` for how it combines with `set_trailing_slash`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{CaseSensitivity, RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// // `/About` is redirected to `/about`, and `/Users/Bob` to `/users/Bob`
+    /// let router = RouteBuilder::new()
+    ///     .set_case_sensitivity(CaseSensitivity::Redirect(StatusCode::MovedPermanently))
+    ///     .get("/about", page)
+    ///     .get("/users/:name", page)
+    ///     .finalize();
+    ///
+    /// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("page"),
+    ///    ))
+    /// }
+    /// ```
+    pub fn set_case_sensitivity(mut self, case_sensitivity: CaseSensitivity) -> Self {
+        self.case_sensitivity = case_sensitivity;
+        self
     }
 
-    pub fn get<H>(self, path: &str, handler: H) -> Self
-    where
-        H: Sized + node::Handler,
-    {
-        self.add(Method::Get, path, handler)
+    /// Serves the explanation of how a request would be routed at the given path, as plain text,
+    /// see `Router::explain`. It is meant for development only and is disabled by default.
+    ///
+    /// The request to explain is given by the query string: `path` being its path, required,
+    /// `method` its method, `GET` if not given, and `host` the host it is sent to, if any. A
+    /// request without a `path` is answered with `400 Bad Request`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// // `/_explain?method=POST&path=/users/5` explains how `POST /users/5` is routed
+    /// let router = RouteBuilder::new()
+    ///     .set_explain_path("/_explain")
+    ///     .get("/users/:id", user)
+    ///     .finalize();
+    ///
+    /// fn user(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("user"),
+    ///    ))
+    /// }
+    /// ```
+    pub fn set_explain_path(mut self, path: &str) -> Self {
+        self.explain_path = Some(path.to_owned());
+        self
     }
 
-    pub fn get_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    /// Registers the routes added by the given closure for requests sent to the matching host only.
+    ///
+    /// The host may be exact eg. `api.example.com`, capture a label into a parameter eg.
+    /// `:tenant.example.com`, or start with a wildcard matching one or more labels eg.
+    /// `*.example.com`. Hosts are compared regardless of ASCII case and without their port.
+    ///
+    /// Exact hosts are tried first, then those with parameters and then wildcard hosts, each in
+    /// the order they were first registered, and finally the routes registered without a host;
+    /// the first to have a route matching the request handles it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let router = RouteBuilder::new()
+    ///     .host("api.example.com", |b| b.get("/status", page))
+    ///     .host(":tenant.example.com", |b| b.get("/", page))
+    ///     .get("/health", page)
+    ///     .finalize();
+    ///
+    /// // `tenant` is captured for `http://acme.example.com/`
+    /// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    let tenant: Option<String> = data.param("tenant").ok();
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body(format!("{:?}", tenant)),
+    ///    ))
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_host` returns an error instead.
+    #[track_caller]
+    pub fn host<F>(self, host: &str, routes: F) -> Self
     where
-        H: Sized + node::Handler,
-        MW: Sized + Middleware + 'static,
+        F: FnOnce(RouteBuilder) -> RouteBuilder,
     {
-        let mut mw: Vec<Box<Middleware>> = Vec::new();
-        for m in middleware {
-            mw.push(Box::new(m));
+        match self.try_host(host, |b| Ok(routes(b))) {
+            Ok(builder) => builder,
+            Err((_, e)) => panic!("{}", e),
         }
-        self.add_with_middleware(Method::Get, path, handler, Some(mw))
     }
 
-    pub fn head<H>(self, path: &str, handler: H) -> Self
+    /// Registers the routes added by the given closure for requests sent to the matching host
+    /// only, returning a `RouteError` along with the builder instead of panicking if the host or
+    /// a route is invalid.
+    ///
+    /// See `RouteBuilder::host` for how hosts are matched.
+    pub fn try_host<F>(mut self, host: &str, routes: F) -> Result<Self, (Self, RouteError)>
     where
-        H: Sized + node::Handler,
+        F: FnOnce(RouteBuilder) -> Result<RouteBuilder, (RouteBuilder, RouteError)>,
     {
-        self.add(Method::Head, path, handler)
-    }
+        let pattern = match HostPattern::parse(host) {
+            Ok(pattern) => pattern,
+            Err(e) => return Err((self, e)),
+        };
+        let index = match self.hosts.iter().position(|h| h.0.as_str() == pattern.as_str()) {
+            Some(i) => i,
+            None => {
+                self.hosts.push((pattern, Routes::new()));
+                self.hosts.len() - 1
+            }
+        };
 
-    pub fn head_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
-    where
-        H: Sized + node::Handler,
-        MW: Sized + Middleware + 'static,
-    {
-        let mut mw: Vec<Box<Middleware>> = Vec::new();
-        for m in middleware {
-            mw.push(Box::new(m));
-        }
-        self.add_with_middleware(Method::Head, path, handler, Some(mw))
+        let previous = self.host.replace(index);
+        restore(routes(self), |b| b.host = previous)
     }
 
-    pub fn post<H>(self, path: &str, handler: H) -> Self
+    /// Registers the routes added by the given closure under the prefix, wrapped by the middleware
+    /// added within the closure using `with_middleware`.
+    ///
+    /// Groups may be nested, in which case the prefixes are joined and the middleware of the outer
+    /// group wraps that of the inner group, which in turn wraps the middleware given for a route.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{Handler, RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// // registers `/api/v1/users` and `/api/v1/admin/stats`, all wrapped by `auth`
+    /// let router = RouteBuilder::new()
+    ///     .group("/api/v1", |g| {
+    ///         g.with_middleware(auth)
+    ///             .get("/users", page)
+    ///             .group("/admin", |g| g.get("/stats", page))
+    ///     })
+    ///     .finalize();
+    ///
+    /// fn auth(handler: Box<Handler>) -> Box<Handler> {
+    ///     handler
+    /// }
+    ///
+    /// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("page"),
+    ///    ))
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_group` returns an error instead.
+    #[track_caller]
+    pub fn group<F>(self, prefix: &str, routes: F) -> Self
     where
-        H: Sized + node::Handler,
+        F: FnOnce(RouteBuilder) -> RouteBuilder,
     {
-        self.add(Method::Post, path, handler)
+        match self.try_group(prefix, |b| Ok(routes(b))) {
+            Ok(builder) => builder,
+            Err((_, e)) => panic!("{}", e),
+        }
     }
 
-    pub fn post_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    /// Registers the routes added by the given closure under the prefix, returning a `RouteError`
+    /// along with the builder instead of panicking if the prefix or a route is invalid.
+    ///
+    /// See `RouteBuilder::group` for how groups are registered.
+    pub fn try_group<F>(mut self, prefix: &str, routes: F) -> Result<Self, (Self, RouteError)>
     where
-        H: Sized + node::Handler,
-        MW: Sized + Middleware + 'static,
+        F: FnOnce(RouteBuilder) -> Result<RouteBuilder, (RouteBuilder, RouteError)>,
     {
-        let mut mw: Vec<Box<Middleware>> = Vec::new();
-        for m in middleware {
-            mw.push(Box::new(m));
+        if !prefix.is_empty() && !prefix.starts_with('/') {
+            let e = RouteError::MissingLeadingSlash {
+                pattern: prefix.to_owned(),
+            };
+            return Err((self, e));
         }
-        self.add_with_middleware(Method::Post, path, handler, Some(mw))
+
+        self.groups.push(Group {
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            middleware: Vec::new(),
+        });
+        restore(routes(self), |b| {
+            b.groups.pop();
+        })
     }
 
-    pub fn put<H>(self, path: &str, handler: H) -> Self
+    /// Registers the routes added by the given closure along with conditions on the headers or the
+    /// query of the requests they handle, eg. a media type the request must accept.
+    ///
+    /// Several routes may share a method and path as long as their conditions differ; a request
+    /// is handled by the first of them, in the order they were registered, whose conditions it
+    /// satisfies, the route without conditions if any being tried last. If it satisfies none of
+    /// them, it is answered with `415 Unsupported Media Type` when any of them rejects its
+    /// `Content-Type`, otherwise `406 Not Acceptable` when any of them rejects it for another
+    /// header, and is otherwise handled by the not found handler as it satisfies the query
+    /// conditions of none. Conditions only select among routes sharing the path matched, other
+    /// routes are not tried instead.
+    ///
+    /// A route requiring a media type to be accepted is preferred only by requests listing it in
+    /// their `Accept` header. A request accepting it through a range such as `*/*`, or having no
+    /// `Accept` header, is handled by the route only when it satisfies no other route, eg. when no
+    /// route without conditions shares the method and path.
+    ///
+    /// Conditions are compared as a set, so registering the same conditions twice for a method
+    /// and path is a duplicate whatever their order.
+    ///
+    /// Conditions may be nested, in which case a request must satisfy both.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{Conditions, RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// // `Accept: application/vnd.api.v2+json` is handled by `v2`, any other request by `v1`
+    /// let router = RouteBuilder::new()
+    ///     .when(Conditions::new().accept("application/vnd.api.v2+json"), |b| {
+    ///         b.get("/users", v2)
+    ///     })
+    ///     .get("/users", v1)
+    ///     .when(Conditions::new().content_type("application/json"), |b| {
+    ///         b.post("/users", v1)
+    ///     })
+    ///     .finalize();
+    ///
+    /// fn v1(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(Response::new().with_body("v1")))
+    /// }
+    ///
+    /// fn v2(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(Response::new().with_body("v2")))
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_when` returns an error instead.
+    #[track_caller]
+    pub fn when<F>(self, conditions: Conditions, routes: F) -> Self
     where
-        H: Sized + node::Handler,
+        F: FnOnce(RouteBuilder) -> RouteBuilder,
     {
-        self.add(Method::Put, path, handler)
+        match self.try_when(conditions, |b| Ok(routes(b))) {
+            Ok(builder) => builder,
+            Err((_, e)) => panic!("{}", e),
+        }
     }
 
-    pub fn put_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    /// Registers the routes added by the given closure along with conditions on the headers or the
+    /// query of the requests they handle, returning a `RouteError` along with the builder instead
+    /// of panicking if a route is invalid.
+    ///
+    /// See `RouteBuilder::when` for how conditions select the route handling a request.
+    pub fn try_when<F>(
+        mut self,
+        conditions: Conditions,
+        routes: F,
+    ) -> Result<Self, (Self, RouteError)>
     where
-        H: Sized + node::Handler,
-        MW: Sized + Middleware + 'static,
+        F: FnOnce(RouteBuilder) -> Result<RouteBuilder, (RouteBuilder, RouteError)>,
     {
-        let mut mw: Vec<Box<Middleware>> = Vec::new();
-        for m in middleware {
-            mw.push(Box::new(m));
-        }
-        self.add_with_middleware(Method::Put, path, handler, Some(mw))
+        let previous = self.conditions.clone();
+        self.conditions = previous.and(&conditions);
+        restore(routes(self), |b| b.conditions = previous)
     }
 
-    pub fn delete<H>(self, path: &str, handler: H) -> Self
+    /// Mounts the router under the prefix, handling every request for the prefix or a path below
+    /// it using the router, eg. `/billing/invoices/5` is handled as `/invoices/5`.
+    ///
+    /// The mounted router applies its own middleware and not found handler, and is wrapped by the
+    /// global middleware of this router like any other route. Routes registered on this router
+    /// below the prefix take priority over the mounted router. Parameters captured from the prefix
+    /// are not passed on to the mounted router, and requests using an extension method are not
+    /// mounted. The mounted router redirects below the prefix, eg. `/billing/invoices/` to
+    /// `/billing/invoices` when redirecting trailing slashes, and its handlers may read the prefix
+    /// as requested using `RequestData::base_path`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let billing = RouteBuilder::new()
+    ///     .get("/invoices/:id", page)
+    ///     .finalize();
+    ///
+    /// // `/billing/invoices/5` is handled by `billing` as `/invoices/5`
+    /// let router = RouteBuilder::new()
+    ///     .get("/", page)
+    ///     .mount("/billing", billing)
+    ///     .finalize();
+    ///
+    /// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("page"),
+    ///    ))
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_mount` returns an error instead.
+    #[track_caller]
+    pub fn mount(self, prefix: &str, router: Router) -> Self {
+        match self.try_mount(prefix, router) {
+            Ok(builder) => builder,
+            Err((_, e)) => panic!("{}", e),
+        }
+    }
+
+    /// Mounts the router under the prefix, returning a `RouteError` along with the builder instead
+    /// of panicking if the prefix is invalid or conflicts with the routes already registered.
+    ///
+    /// See `RouteBuilder::mount` for how requests are handled.
+    #[track_caller]
+    pub fn try_mount(mut self, prefix: &str, router: Router) -> Result<Self, (Self, RouteError)> {
+        let location = Location::caller();
+        if !prefix.starts_with('/') {
+            let e = RouteError::MissingLeadingSlash {
+                pattern: prefix.to_owned(),
+            };
+            return Err((self, e));
+        }
+        let prefix = prefix.trim_end_matches('/');
+
+        let mut paths = vec![format!("{}/", prefix), format!("{}/{}", prefix, MOUNT_PATH)];
+        if !prefix.is_empty() {
+            paths.push(prefix.to_owned());
+        }
+        // the routes are mounted as a whole, none is kept if one of them is invalid
+        let saved = self.routes().clone();
+        for method in MOUNT_METHODS {
+            for path in &paths {
+                let handler = Mount {
+                    router: router.handler.clone(),
+                    prefix: prefix.to_owned(),
+                };
+                let info = RouteInfo::new(method.clone(), path, Some(location));
+                if let Err(e) = self.register(info, handler, None, false) {
+                    *self.routes() = saved;
+                    return Err((self, e));
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Registers the handler for the given method and path, wrapped by the given middleware.
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_add_with_middleware` returns an error
+    /// instead.
+    #[track_caller]
+    pub fn add_with_middleware<H>(
+        self,
+        method: Method,
+        path: &str,
+        handler: H,
+        middleware: Option<Vec<Box<Middleware>>>,
+    ) -> Self
     where
         H: Sized + node::Handler,
     {
-        self.add(Method::Delete, path, handler)
+        match self.try_add_with_middleware(method, path, handler, middleware) {
+            Ok(builder) => builder,
+            Err((_, e)) => panic!("{}", e),
+        }
     }
 
-    pub fn delete_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    /// Registers the handler for the given method and path, wrapped by the given middleware,
+    /// returning a `RouteError` instead of panicking if the route is invalid.
+    ///
+    /// The builder is returned along with the error, unchanged, so that an invalid route may be
+    /// reported and skipped while registering the others.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder, RouteError};
+    /// use hyper::{Error, Method, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let (builder, err) = RouteBuilder::new()
+    ///     .try_add(Method::Get, "/user/:id(\\d+[)", root)
+    ///     .err()
+    ///     .unwrap();
+    ///
+    /// // the builder may still be used to register other routes
+    /// let router = builder.get("/", root).finalize();
+    ///
+    /// match err {
+    ///     RouteError::InvalidSegment { pattern, segment, .. } => {
+    ///         assert_eq!(pattern, "/user/:id(\\d+[)");
+    ///         assert_eq!(segment, ":id(\\d+[)");
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    ///
+    /// fn root(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("root"),
+    ///    ))
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn try_add_with_middleware<H>(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: H,
+        middleware: Option<Vec<Box<Middleware>>>,
+    ) -> Result<Self, (Self, RouteError)>
     where
         H: Sized + node::Handler,
-        MW: Sized + Middleware + 'static,
     {
-        let mut mw: Vec<Box<Middleware>> = Vec::new();
-        for m in middleware {
-            mw.push(Box::new(m));
+        let info = RouteInfo::new(method, path, Some(Location::caller()));
+        match self.register(info, handler, middleware, false) {
+            Ok(()) => Ok(self),
+            Err(e) => Err((self, e)),
         }
-        self.add_with_middleware(Method::Delete, path, handler, Some(mw))
     }
 
-    pub fn connect<H>(self, path: &str, handler: H) -> Self
+    /// Registers the handler for the given method and path, replacing any handler previously
+    /// registered for them instead of treating it as a duplicate.
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_replace` returns an error instead.
+    #[track_caller]
+    pub fn replace<H>(self, method: Method, path: &str, handler: H) -> Self
     where
         H: Sized + node::Handler,
     {
-        self.add(Method::Connect, path, handler)
+        match self.try_replace(method, path, handler) {
+            Ok(builder) => builder,
+            Err((_, e)) => panic!("{}", e),
+        }
     }
 
-    pub fn connect_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    /// Registers the handler for the given method and path, replacing any handler previously
+    /// registered for them, returning a `RouteError` along with the builder instead of panicking if
+    /// the route is invalid.
+    #[track_caller]
+    pub fn try_replace<H>(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: H,
+    ) -> Result<Self, (Self, RouteError)>
+    where
+        H: Sized + node::Handler,
+    {
+        let info = RouteInfo::new(method, path, Some(Location::caller()));
+        match self.register(info, handler, None, true) {
+            Ok(()) => Ok(self),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    // registers the route described by `info`, whose pattern is completed by the prefix of the
+    // enclosing groups, along with its host, middleware and name if any. Nothing is registered
+    // if an error is returned.
+    fn register<H>(
+        &mut self,
+        mut info: RouteInfo,
+        handler: H,
+        middleware: Option<Vec<Box<Middleware>>>,
+        replace: bool,
+    ) -> Result<(), RouteError>
+    where
+        H: Sized + node::Handler,
+    {
+        if !info.pattern.starts_with('/') {
+            return Err(RouteError::MissingLeadingSlash {
+                pattern: info.pattern,
+            });
+        }
+        info.pattern = self.prefix(&info.pattern);
+        let path = &info.pattern.clone();
+        if let Some(ref name) = info.name {
+            if let Some(route) = self.names.get(name) {
+                if route != path {
+                    return Err(RouteError::DuplicateName {
+                        name: name.to_owned(),
+                        pattern: path.to_owned(),
+                        route: route.clone(),
+                    });
+                }
+            }
+        }
+
+        // the last expansion is the longest and so contains every parameter
+        let paths = param::expand_optional(path)?;
+        let full = paths.last().unwrap();
+
+        // names are looked up without their sigil, so `:path` and `*path` clash
+        let mut params: HashMap<String, bool> = HashMap::new();
+        if let Some(i) = self.host {
+            for p in self.hosts[i].0.params() {
+                params.insert(p[1..].to_owned(), true);
+            }
+        }
+        let mut ids = Vec::new();
+        for segment in full.split("/") {
+            if segment.starts_with('*') {
+                ids.push(param::split_wildcard(segment).0.to_owned());
+            } else if let Ok(pieces) = param::parse_segment(segment) {
+                for p in pieces {
+                    if let param::Piece::Param(id, _) = p {
+                        ids.push(id);
+                    }
+                }
+            }
+        }
+        for id in ids {
+            // an unnamed wildcard captures nothing to look up
+            if id.len() < 2 {
+                continue;
+            }
+            if params.insert(id[1..].to_owned(), true).is_some() {
+                return Err(RouteError::DuplicateParam {
+                    pattern: path.to_owned(),
+                    param: id,
+                });
+            }
+        }
+
+        let mut h: Box<node::Handler> = Box::new(handler);
+
+        // middleware just for this handler
+        if let Some(mw) = middleware {
+            for m in mw.iter().rev() {
+                h = m.next(h);
+                info.middleware += 1;
+            }
+        }
+
+        // middleware of the enclosing groups, the outermost wrapping the others
+        for group in self.groups.iter().rev() {
+            for m in group.middleware.iter().rev() {
+                h = m.next(h);
+                info.middleware += 1;
+            }
+        }
+
+        info.host = self.host.map(|i| self.hosts[i].0.as_str().to_owned());
+        info.conditions = self.conditions.clone();
+        let name = info.name.clone();
+        let method = info.method.clone();
+        let route = Route {
+            handler: Arc::new(h),
+            info,
+        };
+
+        // every expansion is added to a copy first, so that none is kept if one of them fails
+        let tree = self.routes();
+        let mut node = tree.get(&method).cloned().unwrap_or_else(Node::new);
+        for p in &paths {
+            node.add(&p[1..], route.clone(), replace)?;
+        }
+        tree.insert(method, node);
+        if let Some(name) = name {
+            self.names.insert(name, path.to_owned());
+        }
+        Ok(())
+    }
+
+    // returns the routes of the host they are currently being registered for, if any.
+    fn routes(&mut self) -> &mut Routes {
+        match self.host {
+            Some(i) => &mut self.hosts[i].1,
+            None => &mut self.tree,
+        }
+    }
+
+    /// Registers the routes described by the config file, its format told by its extension
+    /// `.toml` or `.json`, resolving the names of their handlers and middleware using the
+    /// registry. See `RouteBuilder::parse_config` for the format of the file.
+    pub fn load_config<P: AsRef<Path>>(
+        self,
+        path: P,
+        registry: &HandlerRegistry,
+    ) -> Result<Self, ConfigError> {
+        let (format, source) = config::read(path.as_ref())?;
+        self.parse_config(&source, format, registry)
+    }
+
+    /// Registers the routes described by the source, resolving the names of their handlers and
+    /// middleware using the registry.
+    ///
+    /// The source must list `routes`, each having a `method` and a `path` along with either the
+    /// name of its `handler`, or the location to `redirect` to with an optional redirect `status`
+    /// among 301, 302, 303, 307 and 308, 301 by default. A route may also list the names of its
+    /// `middleware`, be given a `name`, and be disabled using `enabled = false`; a disabled route
+    /// is checked but not registered.
+    ///
+    /// Routes are registered within the enclosing host and groups, if any. An error is returned,
+    /// with the line of the source it was found at, if the source cannot be parsed, a handler or
+    /// middleware is not registered, or a route is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{ConfigFormat, HandlerRegistry, RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let config = r#"
+    /// [[routes]]
+    /// method = "GET"
+    /// path = "/users/:id"
+    /// handler = "user"
+    /// name = "user"
+    ///
+    /// [[routes]]
+    /// method = "GET"
+    /// path = "/people/:id"
+    /// redirect = "/users"
+    /// status = 308
+    /// "#;
+    ///
+    /// let registry = HandlerRegistry::new().handler("user", user);
+    /// let router = RouteBuilder::new()
+    ///     .parse_config(config, ConfigFormat::Toml, &registry)
+    ///     .unwrap()
+    ///     .finalize();
+    ///
+    /// fn user(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("user"),
+    ///    ))
+    /// }
+    /// ```
+    pub fn parse_config(
+        self,
+        source: &str,
+        format: ConfigFormat,
+        registry: &HandlerRegistry,
+    ) -> Result<Self, ConfigError> {
+        config::load(self, source, format, registry)
+    }
+
+    // returns the path prefixed by the enclosing groups.
+    fn prefix(&self, path: &str) -> String {
+        let mut prefixed: String = self.groups.iter().map(|g| g.prefix.as_str()).collect();
+        prefixed.push_str(path);
+        prefixed
+    }
+
+    /// Registers the handler for the given method and path under the name, which may then be used
+    /// to build the url of the route using `Router::url_for`.
+    ///
+    /// The same name may be given to routes for differing methods sharing the same path.
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_add_named` returns an error instead.
+    #[track_caller]
+    pub fn add_named<H>(self, method: Method, name: &str, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        match self.try_add_named(method, name, path, handler) {
+            Ok(builder) => builder,
+            Err((_, e)) => panic!("{}", e),
+        }
+    }
+
+    /// Registers the handler for the given method and path under the name, returning a
+    /// `RouteError` along with the builder instead of panicking if the route is invalid or the
+    /// name was already given to another path.
+    #[track_caller]
+    pub fn try_add_named<H>(
+        mut self,
+        method: Method,
+        name: &str,
+        path: &str,
+        handler: H,
+    ) -> Result<Self, (Self, RouteError)>
+    where
+        H: Sized + node::Handler,
+    {
+        let mut info = RouteInfo::new(method, path, Some(Location::caller()));
+        info.name = Some(name.to_owned());
+        match self.register(info, handler, None, false) {
+            Ok(()) => Ok(self),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    /// Registers the handler for the given method and path, returning a `RouteError` along with
+    /// the builder instead of panicking if the route is invalid.
+    #[track_caller]
+    pub fn try_add<H>(
+        self,
+        method: Method,
+        path: &str,
+        handler: H,
+    ) -> Result<Self, (Self, RouteError)>
+    where
+        H: Sized + node::Handler,
+    {
+        self.try_add_with_middleware(method, path, handler, None)
+    }
+
+    #[track_caller]
+    pub fn add<H>(self, method: Method, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add_with_middleware(method, path, handler, None)
+    }
+
+    #[track_caller]
+    pub fn get<H>(self, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add(Method::Get, path, handler)
+    }
+
+    /// Registers the handler for the `GET` method and path under the name, see `add_named`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let router = RouteBuilder::new()
+    ///     .get_named("user_profile", "/users/:id", user)
+    ///     .finalize();
+    ///
+    /// assert_eq!(router.url_for("user_profile", &[("id", "42")]).unwrap(), "/users/42");
+    ///
+    /// fn user(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("user"),
+    ///    ))
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn get_named<H>(self, name: &str, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add_named(Method::Get, name, path, handler)
+    }
+
+    #[track_caller]
+    pub fn get_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
     where
         H: Sized + node::Handler,
         MW: Sized + Middleware + 'static,
@@ -269,17 +1076,19 @@ impl RouteBuilder {
         for m in middleware {
             mw.push(Box::new(m));
         }
-        self.add_with_middleware(Method::Connect, path, handler, Some(mw))
+        self.add_with_middleware(Method::Get, path, handler, Some(mw))
     }
 
-    pub fn options<H>(self, path: &str, handler: H) -> Self
+    #[track_caller]
+    pub fn head<H>(self, path: &str, handler: H) -> Self
     where
         H: Sized + node::Handler,
     {
-        self.add(Method::Options, path, handler)
+        self.add(Method::Head, path, handler)
     }
 
-    pub fn options_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    #[track_caller]
+    pub fn head_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
     where
         H: Sized + node::Handler,
         MW: Sized + Middleware + 'static,
@@ -288,17 +1097,19 @@ impl RouteBuilder {
         for m in middleware {
             mw.push(Box::new(m));
         }
-        self.add_with_middleware(Method::Options, path, handler, Some(mw))
+        self.add_with_middleware(Method::Head, path, handler, Some(mw))
     }
 
-    pub fn trace<H>(self, path: &str, handler: H) -> Self
+    #[track_caller]
+    pub fn post<H>(self, path: &str, handler: H) -> Self
     where
         H: Sized + node::Handler,
     {
-        self.add(Method::Trace, path, handler)
+        self.add(Method::Post, path, handler)
     }
 
-    pub fn trace_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    #[track_caller]
+    pub fn post_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
     where
         H: Sized + node::Handler,
         MW: Sized + Middleware + 'static,
@@ -307,17 +1118,19 @@ impl RouteBuilder {
         for m in middleware {
             mw.push(Box::new(m));
         }
-        self.add_with_middleware(Method::Trace, path, handler, Some(mw))
+        self.add_with_middleware(Method::Post, path, handler, Some(mw))
     }
 
-    pub fn patch<H>(self, path: &str, handler: H) -> Self
+    #[track_caller]
+    pub fn put<H>(self, path: &str, handler: H) -> Self
     where
         H: Sized + node::Handler,
     {
-        self.add(Method::Patch, path, handler)
+        self.add(Method::Put, path, handler)
     }
 
-    pub fn patch_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    #[track_caller]
+    pub fn put_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
     where
         H: Sized + node::Handler,
         MW: Sized + Middleware + 'static,
@@ -326,88 +1139,452 @@ impl RouteBuilder {
         for m in middleware {
             mw.push(Box::new(m));
         }
-        self.add_with_middleware(Method::Patch, path, handler, Some(mw))
+        self.add_with_middleware(Method::Put, path, handler, Some(mw))
+    }
+
+    #[track_caller]
+    pub fn delete<H>(self, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add(Method::Delete, path, handler)
+    }
+
+    #[track_caller]
+    pub fn delete_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    where
+        H: Sized + node::Handler,
+        MW: Sized + Middleware + 'static,
+    {
+        let mut mw: Vec<Box<Middleware>> = Vec::new();
+        for m in middleware {
+            mw.push(Box::new(m));
+        }
+        self.add_with_middleware(Method::Delete, path, handler, Some(mw))
+    }
+
+    #[track_caller]
+    pub fn connect<H>(self, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add(Method::Connect, path, handler)
     }
 
+    #[track_caller]
+    pub fn connect_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    where
+        H: Sized + node::Handler,
+        MW: Sized + Middleware + 'static,
+    {
+        let mut mw: Vec<Box<Middleware>> = Vec::new();
+        for m in middleware {
+            mw.push(Box::new(m));
+        }
+        self.add_with_middleware(Method::Connect, path, handler, Some(mw))
+    }
+
+    #[track_caller]
+    pub fn options<H>(self, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add(Method::Options, path, handler)
+    }
+
+    #[track_caller]
+    pub fn options_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    where
+        H: Sized + node::Handler,
+        MW: Sized + Middleware + 'static,
+    {
+        let mut mw: Vec<Box<Middleware>> = Vec::new();
+        for m in middleware {
+            mw.push(Box::new(m));
+        }
+        self.add_with_middleware(Method::Options, path, handler, Some(mw))
+    }
+
+    #[track_caller]
+    pub fn trace<H>(self, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add(Method::Trace, path, handler)
+    }
+
+    #[track_caller]
+    pub fn trace_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    where
+        H: Sized + node::Handler,
+        MW: Sized + Middleware + 'static,
+    {
+        let mut mw: Vec<Box<Middleware>> = Vec::new();
+        for m in middleware {
+            mw.push(Box::new(m));
+        }
+        self.add_with_middleware(Method::Trace, path, handler, Some(mw))
+    }
+
+    #[track_caller]
+    pub fn patch<H>(self, path: &str, handler: H) -> Self
+    where
+        H: Sized + node::Handler,
+    {
+        self.add(Method::Patch, path, handler)
+    }
+
+    #[track_caller]
+    pub fn patch_with_middleware<H, MW>(self, path: &str, handler: H, middleware: Vec<MW>) -> Self
+    where
+        H: Sized + node::Handler,
+        MW: Sized + Middleware + 'static,
+    {
+        let mut mw: Vec<Box<Middleware>> = Vec::new();
+        for m in middleware {
+            mw.push(Box::new(m));
+        }
+        self.add_with_middleware(Method::Patch, path, handler, Some(mw))
+    }
+
+    /// Finalizes the routes and wraps them in the global middleware, creating the `Router`.
+    ///
+    /// See `RouteBuilder::new` for when this panics, `try_finalize` returns an error instead.
     pub fn finalize(self) -> Router {
-        let find = Find::new(self.tree, self.not_found);
+        match self.try_finalize() {
+            Ok(router) => router,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Finalizes the routes and wraps them in the global middleware, creating the `Router` or
+    /// returning a `RouteError` if the routes, considered as a whole, are invalid: when two hosts
+    /// registered separately match the same hosts but name their parameters differently, eg.
+    /// `:tenant.example.com` and `:org.example.com`.
+    pub fn try_finalize(self) -> Result<Router, RouteError> {
+        for (i, host) in self.hosts.iter().enumerate() {
+            if let Some(existing) = self.hosts[..i].iter().find(|h| h.0.conflicts(&host.0)) {
+                return Err(RouteError::ConflictingHost {
+                    host: host.0.as_str().to_owned(),
+                    existing: existing.0.as_str().to_owned(),
+                });
+            }
+        }
+
+        let mut hosts = self.hosts;
+        hosts.sort_by_key(|h| h.0.rank());
+        let table = Arc::new(RouteTable {
+            routes: self.tree,
+            hosts,
+        });
+
+        let mut find = Find::new(table.clone(), self.not_found);
+        find.trailing_slash = self.trailing_slash;
+        find.clean_path = self.clean_path;
+        find.case_sensitivity = self.case_sensitivity;
+        find.explain_path = self.explain_path;
         let mut h: Box<node::Handler> = Box::new(find);
 
         // global middleware
-        if self.middleware.is_some() {
-            let mw = self.middleware.as_ref().unwrap();
-            for i in (0..mw.len()).rev() {
-                h = mw[i].next(h);
+        if let Some(mw) = self.middleware {
+            for m in mw.iter().rev() {
+                h = m.next(h);
             }
         }
-        Router::new(h)
+        let mut router = Router::new(h);
+        router.names = Arc::new(self.names);
+        router.routes = Arc::new(table.infos());
+        router.table = table;
+        Ok(router)
+    }
+}
+
+// the methods a mounted router is registered for.
+const MOUNT_METHODS: &[Method] = &[
+    Method::Get,
+    Method::Head,
+    Method::Post,
+    Method::Put,
+    Method::Delete,
+    Method::Connect,
+    Method::Options,
+    Method::Trace,
+    Method::Patch,
+];
+
+// the wildcard capturing the path below the prefix of a mounted router; it's looked up by its
+// full name, so it cannot be confused with a param of the prefix.
+const MOUNT_PATH: &str = "*_mount_path";
+
+// Mount hands requests over to a mounted router, with the path relative to the mount prefix.
+struct Mount {
+    router: Arc<Box<Handler>>,
+    // the prefix pattern the router is mounted under, without its trailing slash
+    prefix: String,
+}
+
+impl Handler for Mount {
+    fn handle(
+        &self,
+        mut req: Request,
+        params: RequestData,
+    ) -> Box<Future<Item = Response, Error = Error>> {
+        let rest = params
+            .raw_params
+            .as_ref()
+            .and_then(|p| p.get(MOUNT_PATH))
+            .map_or("", |p| p.as_str());
+
+        // the prefix as requested, so that the mounted router can redirect below it; the request
+        // path only differs from the one routed when cleaned, in which case the prefix pattern is
+        // used instead
+        let mut base = params.base.clone();
+        match req.path().strip_suffix(rest) {
+            Some(prefix) => base.push_str(prefix.trim_end_matches('/')),
+            None => base.push_str(&self.prefix),
+        }
+
+        let mut uri = String::new();
+        if let (Some(scheme), Some(authority)) = (req.uri().scheme(), req.uri().authority()) {
+            uri = format!("{}://{}", scheme, authority);
+        }
+        uri.push('/');
+        uri.push_str(rest);
+        if let Some(query) = req.query() {
+            uri.push('?');
+            uri.push_str(query);
+        }
+        // the path was already part of a valid uri, so it cannot fail to parse
+        if let Ok(uri) = uri.parse() {
+            req.set_uri(uri);
+        }
+        let params = RequestData {
+            base,
+            ..RequestData::default()
+        };
+        self.router.handle(req, params)
+    }
+}
+
+struct Group {
+    prefix: String,
+    middleware: Vec<Box<Middleware>>,
+}
+
+// restores the state a scoped closure was called with on the builder it returned, whether it
+// registered its routes or failed to.
+fn restore<F>(
+    result: Result<RouteBuilder, (RouteBuilder, RouteError)>,
+    f: F,
+) -> Result<RouteBuilder, (RouteBuilder, RouteError)>
+where
+    F: FnOnce(&mut RouteBuilder),
+{
+    match result {
+        Ok(mut builder) => {
+            f(&mut builder);
+            Ok(builder)
+        }
+        Err((mut builder, e)) => {
+            f(&mut builder);
+            Err((builder, e))
+        }
     }
 }
 
 struct Find {
-    tree: Routes,
+    table: Arc<RouteTable>,
     not_found: Box<node::Handler>,
+    trailing_slash: TrailingSlash,
+    clean_path: CleanPath,
+    case_sensitivity: CaseSensitivity,
+    explain_path: Option<String>,
 }
 
 impl Find {
-    pub fn new(tree: Routes, not_found: Box<Handler>) -> Self {
-        Find { tree, not_found }
+    pub fn new(table: Arc<RouteTable>, not_found: Box<Handler>) -> Self {
+        Find {
+            table,
+            not_found,
+            trailing_slash: TrailingSlash::Strict,
+            clean_path: CleanPath::Off,
+            case_sensitivity: CaseSensitivity::Sensitive,
+            explain_path: None,
+        }
     }
 }
 
+// returns the first match found using the given lookup among the routes for the method, along
+// with the parameters captured from the host.
+fn find_in<'a, F>(
+    routes: &[HostRoutes<'a, '_>],
+    method: &Method,
+    find: F,
+) -> Option<Match<'a>>
+where
+    F: Fn(&'a Node) -> Option<Match<'a>>,
+{
+    for &(_, tree, ref params) in routes {
+        if let Some(mut m) = tree.get(method).and_then(&find) {
+            for &(id, value) in params {
+                m = m.with_param(id, value);
+            }
+            return Some(m);
+        }
+    }
+    None
+}
+
 impl Handler for Find {
     fn handle(
         &self,
         req: Request,
         params: RequestData,
     ) -> Box<Future<Item = Response, Error = Error>> {
-        let p = req.path().to_owned();
+        if self.explain_path.as_deref() == Some(req.path()) {
+            return explain_request(&self.table, req.query());
+        }
+        let mut p = req.path().to_owned();
+        if self.clean_path != CleanPath::Off {
+            if let Some(clean) = options::clean_path(&p) {
+                if let CleanPath::Redirect(status) = self.clean_path {
+                    return redirect(status, &params.base, &clean[1..], req.query());
+                }
+                p = clean;
+            }
+        }
         let (_, right) = p.split_at(1);
 
-        let node = self.tree.get(req.method());
-        if node.is_none() {
-            return handle_method_not_allowed_not_found(
-                &self.tree,
-                &self.not_found,
-                req,
-                params,
-                right,
-            );
+        let host = host::request_host(&req);
+        let routes = self.table.routes_for(host.as_deref());
+        let method = req.method().clone();
+
+        if let Some(m) = find_in(&routes, &method, |node| node.find(right)) {
+            return serve(m, req, &params.base, self.not_found.as_ref());
         }
-        let m = node.unwrap().find(right);
-        if m.is_none() {
-            return handle_method_not_allowed_not_found(
-                &self.tree,
-                &self.not_found,
-                req,
-                params,
-                right,
-            );
+        if self.case_sensitivity != CaseSensitivity::Sensitive {
+            if let Some(m) = find_in(&routes, &method, |node| node.find_ignore_case(right)) {
+                if let CaseSensitivity::Redirect(status) = self.case_sensitivity {
+                    if let Some(canonical) = m.canonical_path(right) {
+                        return redirect(status, &params.base, &canonical, req.query());
+                    }
+                }
+                return serve(m, req, &params.base, self.not_found.as_ref());
+            }
+        }
+        if self.trailing_slash != TrailingSlash::Strict {
+            if let Some(alt) = options::toggle_trailing_slash(right) {
+                if let Some(m) = find_in(&routes, &method, |node| node.find(&alt)) {
+                    if let TrailingSlash::Redirect(status) = self.trailing_slash {
+                        return redirect(status, &params.base, &alt, req.query());
+                    }
+                    return serve(m, req, &params.base, self.not_found.as_ref());
+                }
+                // both fallbacks combined, eg. `/About/` for `/about`, each correcting the path
+                // it redirects for
+                if self.case_sensitivity != CaseSensitivity::Sensitive {
+                    if let Some(m) = find_in(&routes, &method, |node| node.find_ignore_case(&alt)) {
+                        let canonical = m.canonical_path(&alt).unwrap_or_else(|| alt.clone());
+                        let target = match (self.case_sensitivity, self.trailing_slash) {
+                            (CaseSensitivity::Redirect(status), TrailingSlash::Redirect(_)) => {
+                                Some((status, canonical))
+                            }
+                            (CaseSensitivity::Redirect(status), _) => {
+                                options::toggle_trailing_slash(&canonical).map(|p| (status, p))
+                            }
+                            (_, TrailingSlash::Redirect(status)) => Some((status, alt)),
+                            _ => None,
+                        };
+                        if let Some((status, path)) = target {
+                            return redirect(status, &params.base, &path, req.query());
+                        }
+                        return serve(m, req, &params.base, self.not_found.as_ref());
+                    }
+                }
+            }
+        }
+        // another method is allowed if it matches the path any way the options let it match
+        let mut paths = vec![right.to_owned()];
+        if self.trailing_slash != TrailingSlash::Strict {
+            paths.extend(options::toggle_trailing_slash(right));
+        }
+        let ignore_case = self.case_sensitivity != CaseSensitivity::Sensitive;
+        let trees: Vec<&Routes> = routes.iter().map(|r| r.1).collect();
+        handle_method_not_allowed_not_found(
+            &trees,
+            &self.not_found,
+            req,
+            params,
+            &paths,
+            ignore_case,
+        )
+    }
+}
+
+// handles the request using the first route matched whose conditions it satisfies, along with
+// the query values they required, or responds with the status given by `Match::select` if it
+// satisfies none; a request satisfying the query conditions of no route being not found. `base`
+// is the prefix the router is mounted under, if any.
+fn serve(
+    m: Match<'_>,
+    req: Request,
+    base: &str,
+    not_found: &Handler,
+) -> Box<Future<Item = Response, Error = hyper::Error>> {
+    match m.select(&req) {
+        Ok(route) => {
+            let mut params = m.params;
+            params.query = route
+                .info
+                .conditions
+                .matched_query(&req)
+                .filter(|q| !q.is_empty());
+            params.base = base.to_owned();
+            route.handler.handle(req, params)
+        }
+        Err(StatusCode::NotFound) => {
+            let params = RequestData {
+                base: base.to_owned(),
+                ..RequestData::default()
+            };
+            not_found.handle(req, params)
+        }
+        Err(status) => {
+            let reason = status.canonical_reason().unwrap_or("");
+            Box::new(futures::future::ok(
+                Response::new()
+                    .with_status(status)
+                    .with_header(ContentLength(reason.len() as u64))
+                    .with_body(reason),
+            ))
         }
-        let m = m.unwrap();
-        m.handler.handle(req, m.params)
     }
 }
 
 fn handle_method_not_allowed_not_found(
-    tree: &Routes,
+    trees: &[&Routes],
     not_found: &Box<node::Handler>,
     req: Request,
     params: RequestData,
-    path: &str,
+    paths: &[String],
+    ignore_case: bool,
 ) -> Box<Future<Item = Response, Error = hyper::Error>> {
     const METHOD_NOT_ALLOWED: &'static str = "Method Not Allowed";
     let mut found = false;
     let mut methods: Vec<Method> = Vec::new();
 
-    for (k, v) in tree {
-        if k == req.method() {
-            continue;
-        }
-        let m = v.find(path);
-        if m.is_some() {
-            methods.push(k.clone());
-            found = true;
+    for tree in trees {
+        for (k, v) in tree.iter() {
+            if k == req.method() || methods.contains(k) {
+                continue;
+            }
+            let m = paths
+                .iter()
+                .any(|p| v.find(p).is_some() || ignore_case && v.find_ignore_case(p).is_some());
+            if m {
+                methods.push(k.clone());
+                found = true;
+            }
         }
     }
     if found {
@@ -422,6 +1599,71 @@ fn handle_method_not_allowed_not_found(
     not_found.handle(req, params)
 }
 
+// responds with the explanation of how the request described by the query string would be
+// routed, see `RouteBuilder::set_explain_path`.
+fn explain_request(
+    table: &RouteTable,
+    query: Option<&str>,
+) -> Box<Future<Item = Response, Error = hyper::Error>> {
+    const BAD_REQUEST: &str = "Bad Request: missing path";
+    let mut method = Method::Get;
+    let mut path = None;
+    let mut host = None;
+    for (key, value) in conditions::query_pairs(query.unwrap_or("")) {
+        match key.as_str() {
+            "method" => method = value.parse().unwrap_or(Method::Extension(value)),
+            "path" => path = Some(value),
+            "host" => host = Some(value),
+            _ => {}
+        }
+    }
+
+    let body = match path {
+        Some(ref path) if path.starts_with('/') => {
+            let uri = match host {
+                Some(host) => format!("http://{}{}", host, path),
+                None => path.to_owned(),
+            };
+            explain::explain(table, method, &uri).to_string()
+        }
+        _ => {
+            return Box::new(futures::future::ok(
+                Response::new()
+                    .with_status(StatusCode::BadRequest)
+                    .with_header(ContentLength(BAD_REQUEST.len() as u64))
+                    .with_body(BAD_REQUEST),
+            ))
+        }
+    };
+    Box::new(futures::future::ok(
+        Response::new()
+            .with_status(StatusCode::Ok)
+            .with_header(ContentType::plaintext())
+            .with_header(ContentLength(body.len() as u64))
+            .with_body(body),
+    ))
+}
+
+// redirects to the given path, without its leading slash, below the prefix the router is mounted
+// under if any, preserving the query string if any.
+fn redirect(
+    status: StatusCode,
+    base: &str,
+    path: &str,
+    query: Option<&str>,
+) -> Box<Future<Item = Response, Error = hyper::Error>> {
+    let location = match query {
+        Some(q) => format!("{}/{}?{}", base, path, q),
+        None => format!("{}/{}", base, path),
+    };
+    Box::new(futures::future::ok(
+        Response::new()
+            .with_status(status)
+            .with_header(LocationHeader::new(location))
+            .with_header(ContentLength(0)),
+    ))
+}
+
 const NOT_FOUND: &'static str = "Not Found";
 
 fn not_found(_req: Request, _params: RequestData) -> Box<Future<Item = Response, Error = Error>> {
@@ -442,7 +1684,7 @@ mod tests {
     use futures::sync::oneshot::{self, Canceled};
     use futures::{Future, Stream};
     use hyper::Client;
-    use hyper::server::Http;
+    use hyper::server::{Http, NewService, Service};
     use std::str;
     use std::thread;
     use tests::tokio_core::reactor::Core;
@@ -463,6 +1705,34 @@ mod tests {
         }
     }
 
+    // appends its name to the `X-Trace` header of the response, recording the order middleware ran.
+    struct Trace(&'static str);
+
+    impl Middleware for Trace {
+        fn next(&self, handler: Box<Handler>) -> Box<Handler> {
+            let name = self.0;
+            let func = move |req: Request, params: RequestData| {
+                let x = Box::new(handler.handle(req, params).map(move |mut res| {
+                    let trace = match res.headers().get_raw("X-Trace") {
+                        Some(t) => format!("{},{}", str::from_utf8(t.one().unwrap()).unwrap(), name),
+                        None => name.to_owned(),
+                    };
+                    res.headers_mut().set_raw("X-Trace", trace);
+                    res
+                }));
+                let x: Box<Future<Item = Response, Error = hyper::Error>> = x;
+                x
+            };
+            Box::new(func)
+        }
+    }
+
+    fn trace(res: &Response) -> Option<String> {
+        res.headers()
+            .get_raw("X-Trace")
+            .map(|t| str::from_utf8(t.one().unwrap()).unwrap().to_owned())
+    }
+
     fn test(req: Request, _params: RequestData) -> Box<Future<Item = Response, Error = Error>> {
         let body = format!("{}", req.uri());
         Box::new(futures::future::ok(
@@ -473,6 +1743,1493 @@ mod tests {
         ))
     }
 
+    fn echo_params(
+        _req: Request,
+        data: RequestData,
+    ) -> Box<Future<Item = Response, Error = Error>> {
+        let mut params: Vec<String> = data.params
+            .unwrap_or_default()
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        params.sort();
+        let body = params.join(";");
+        Box::new(futures::future::ok(
+            Response::new()
+                .with_status(StatusCode::Ok)
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body),
+        ))
+    }
+
+    // calls the router directly, without going through a server, returning the response.
+    fn call_raw(router: &Router, method: Method, uri: &str) -> Response {
+        let req = Request::new(method, uri.parse().unwrap());
+        router
+            .handler
+            .handle(req, RequestData::default())
+            .wait()
+            .unwrap()
+    }
+
+    // calls the router directly, without going through a server, returning the status and body.
+    fn call(router: &Router, method: Method, uri: &str) -> (StatusCode, String) {
+        let res = call_raw(router, method, uri);
+        let status = res.status();
+        let body = res.body().concat2().wait().unwrap();
+        (status, str::from_utf8(&body).unwrap().to_owned())
+    }
+
+    fn location(res: &Response) -> Option<String> {
+        res.headers()
+            .get::<LocationHeader>()
+            .map(|l| l.to_string())
+    }
+
+    #[test]
+    fn param_and_wild_share_prefix() {
+        let router = RouteBuilder::new()
+            .get("/files/:id", echo_params)
+            .get("/files/:id/meta", echo_params)
+            .get("/files/*rest", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/files/13"),
+            (StatusCode::Ok, ":id=13".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/files/13/meta"),
+            (StatusCode::Ok, ":id=13".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/files/13/data"),
+            (StatusCode::Ok, "*rest=13/data".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/files/13/meta/more"),
+            (StatusCode::Ok, "*rest=13/meta/more".to_owned())
+        );
+        assert_eq!(call(&router, Method::Get, "/files/").0, StatusCode::NotFound);
+        assert_eq!(
+            call(&router, Method::Post, "/files/13/data").0,
+            StatusCode::MethodNotAllowed
+        );
+    }
+
+    #[test]
+    fn percent_decoded_params() {
+        let router = RouteBuilder::new()
+            .get("/users/:name", echo_params)
+            .get("/files/*path", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/users/John%20Doe"),
+            (StatusCode::Ok, ":name=John Doe".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/files/a%20b/c%2Fd"),
+            (StatusCode::Ok, "*path=a b/c%2Fd".to_owned())
+        );
+    }
+
+    #[test]
+    fn constrained_params() {
+        let router = RouteBuilder::new()
+            .get("/orders/:id(\\d+)", echo_params)
+            .get("/orders/:token([A-Z]{8})", echo_params)
+            .get("/users/:name([a-z ]+)", echo_params)
+            .get("/files/:name([a-z ]+).:ext", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/orders/42"),
+            (StatusCode::Ok, ":id=42".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/orders/ABCDEFGH"),
+            (StatusCode::Ok, ":token=ABCDEFGH".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/orders/abc").0,
+            StatusCode::NotFound
+        );
+
+        // constraints are checked against the decoded value
+        assert_eq!(
+            call(&router, Method::Get, "/users/john%20doe"),
+            (StatusCode::Ok, ":name=john doe".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/files/my%20notes.txt"),
+            (StatusCode::Ok, ":ext=txt;:name=my notes".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/orders/%34%32"),
+            (StatusCode::Ok, ":id=42".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/users/john%2Fdoe").0,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn typed_params() {
+        fn user(_req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+            let id: u64 = data.param("id").unwrap();
+            Box::new(futures::future::ok(
+                Response::new()
+                    .with_status(StatusCode::Ok)
+                    .with_body(format!("user {}", id + 1)),
+            ))
+        }
+
+        let router = RouteBuilder::new()
+            .get("/users/:id<u64>", user)
+            .get("/users/:name<str>/profile", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/users/41"),
+            (StatusCode::Ok, "user 42".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/users/joe").0,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/users/joe/profile"),
+            (StatusCode::Ok, ":name=joe".to_owned())
+        );
+    }
+
+    #[test]
+    fn optional_params() {
+        let router = RouteBuilder::new()
+            .get("/archive/:year<u64>?/:month?", echo_params)
+            .get("/:page?", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/archive"),
+            (StatusCode::Ok, "".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/archive/2018"),
+            (StatusCode::Ok, ":year=2018".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/archive/2018/05"),
+            (StatusCode::Ok, ":month=05;:year=2018".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/archive/2018/05/01").0,
+            StatusCode::NotFound
+        );
+        assert_eq!(call(&router, Method::Get, "/"), (StatusCode::Ok, "".to_owned()));
+        assert_eq!(
+            call(&router, Method::Get, "/about"),
+            (StatusCode::Ok, ":page=about".to_owned())
+        );
+    }
+
+    #[test]
+    fn intra_segment_params() {
+        let router = RouteBuilder::new()
+            .get("/download/:name.:ext", echo_params)
+            .get("/@:user", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/download/lars.tar.gz"),
+            (StatusCode::Ok, ":ext=gz;:name=lars.tar".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/@joeybloggs"),
+            (StatusCode::Ok, ":user=joeybloggs".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/download/lars").0,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn non_terminal_wild() {
+        let router = RouteBuilder::new()
+            .get("/repos/*path/blob", echo_params)
+            .get("/assets/*file.map", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/repos/rust-playground/lars/blob"),
+            (StatusCode::Ok, "*path=rust-playground/lars".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/assets/js/app.js.map"),
+            (StatusCode::Ok, "*file=js/app.js".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/repos/rust-playground/lars").0,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_duplicate_intra_segment_param_names_path() {
+        RouteBuilder::new().get("/files/:name/:name.:ext", test);
+    }
+
+    #[test]
+    fn try_add_errors() {
+        fn err(path: &str) -> RouteError {
+            RouteBuilder::new()
+                .try_add(Method::Get, path, test)
+                .err()
+                .unwrap()
+                .1
+        }
+
+        assert_eq!(
+            err("user"),
+            RouteError::MissingLeadingSlash {
+                pattern: "user".to_owned(),
+            }
+        );
+        assert_eq!(
+            err("/user/:id/:id"),
+            RouteError::DuplicateParam {
+                pattern: "/user/:id/:id".to_owned(),
+                param: ":id".to_owned(),
+            }
+        );
+        assert_eq!(
+            err("/:path/*path"),
+            RouteError::DuplicateParam {
+                pattern: "/:path/*path".to_owned(),
+                param: "*path".to_owned(),
+            }
+        );
+        assert_eq!(
+            err("/user/:id<u32>"),
+            RouteError::InvalidSegment {
+                pattern: "/user/:id<u32>".to_owned(),
+                segment: ":id<u32>".to_owned(),
+                reason: "unknown parameter type 'u32'".to_owned(),
+            }
+        );
+        assert_eq!(
+            err("/archive/:year?/posts"),
+            RouteError::InvalidSegment {
+                pattern: "/archive/:year?/posts".to_owned(),
+                segment: "posts".to_owned(),
+                reason: "required segment after an optional parameter".to_owned(),
+            }
+        );
+        match err("/user/*rest/:id") {
+            RouteError::InvalidSegment { segment, .. } => assert_eq!(segment, "*rest"),
+            e => panic!("unexpected error {:?}", e),
+        }
+        match err("/files/:name:ext") {
+            RouteError::InvalidSegment { segment, .. } => assert_eq!(segment, ":name:ext"),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn try_add_conflicts() {
+        let conflict = |first: &str, second: &str| {
+            RouteBuilder::new()
+                .try_add(Method::Get, first, test)
+                .ok()
+                .unwrap()
+                .try_add(Method::Get, second, test)
+                .err()
+                .unwrap()
+                .1
+        };
+
+        assert_eq!(
+            conflict("/user/:foo", "/user/:bar/profile"),
+            RouteError::Conflict {
+                pattern: "/user/:bar/profile".to_owned(),
+                segment: ":bar".to_owned(),
+                existing: ":foo".to_owned(),
+                route: "/user/:foo".to_owned(),
+            }
+        );
+        assert_eq!(
+            conflict("/static/*foo", "/static/*bar"),
+            RouteError::Conflict {
+                pattern: "/static/*bar".to_owned(),
+                segment: "*bar".to_owned(),
+                existing: "*foo".to_owned(),
+                route: "/static/*foo".to_owned(),
+            }
+        );
+        assert_eq!(
+            conflict("/files/:name.:ext", "/files/:base.:suffix"),
+            RouteError::Conflict {
+                pattern: "/files/:base.:suffix".to_owned(),
+                segment: ":base.:suffix".to_owned(),
+                existing: ":name.:ext".to_owned(),
+                route: "/files/:name.:ext".to_owned(),
+            }
+        );
+
+        // the same parameter on another method is not a conflict
+        assert!(
+            RouteBuilder::new()
+                .try_add(Method::Get, "/user/:foo", test)
+                .ok()
+                .unwrap()
+                .try_add(Method::Post, "/user/:bar", test)
+                .ok()
+                .unwrap()
+                .try_finalize()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn try_add_keeps_builder() {
+        // the expansion `/archive` is not kept once `/archive/:year` conflicts, nor is the name
+        let (builder, _) = RouteBuilder::new()
+            .get("/archive/:y", test)
+            .try_add_named(Method::Get, "archive", "/archive/:year?", test)
+            .err()
+            .unwrap();
+        // nor is the `:id` child added before the wildcard is found invalid
+        let (builder, _) = builder
+            .try_add(Method::Get, "/users/:id/*path*", test)
+            .err()
+            .unwrap();
+        let router = builder
+            .get("/users/:name", echo_params)
+            .get_named("archive", "/archives", test)
+            .finalize();
+
+        assert_eq!(call(&router, Method::Get, "/archive").0, StatusCode::NotFound);
+        assert_eq!(
+            call(&router, Method::Get, "/users/bob"),
+            (StatusCode::Ok, ":name=bob".to_owned())
+        );
+    }
+
+    #[test]
+    fn duplicate_routes() {
+        let builder = RouteBuilder::new().get("/users/:id", test);
+        let line = line!() + 1;
+        let err = builder.try_add(Method::Get, "/users/:id", test).err().unwrap().1;
+
+        match err {
+            RouteError::Duplicate {
+                ref method,
+                ref pattern,
+                location,
+                ref route,
+                route_location,
+            } => {
+                assert_eq!(*method, Method::Get);
+                assert_eq!(pattern, "/users/:id");
+                assert_eq!(route, "/users/:id");
+                assert_eq!(location.unwrap().file(), file!());
+                assert_eq!(location.unwrap().line(), line);
+                assert_eq!(route_location.unwrap().line(), line - 2);
+            }
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        assert!(err.to_string().contains(&format!("{}:{}", file!(), line)));
+
+        // an optional parameter expands into a route which may already exist
+        let err = RouteBuilder::new()
+            .get("/archive", test)
+            .try_add(Method::Get, "/archive/:year?", test)
+            .err()
+            .unwrap()
+            .1;
+        match err {
+            RouteError::Duplicate { pattern, route, .. } => {
+                assert_eq!(pattern, "/archive/:year?");
+                assert_eq!(route, "/archive");
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn replace_route() {
+        let router = RouteBuilder::new()
+            .get("/users/:id", test)
+            .replace(Method::Get, "/users/:id", echo_params)
+            .replace(Method::Get, "/new/:id", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/users/13"),
+            (StatusCode::Ok, ":id=13".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/new/13"),
+            (StatusCode::Ok, ":id=13".to_owned())
+        );
+    }
+
+    #[test]
+    fn trailing_slash_strict() {
+        let router = RouteBuilder::new()
+            .get("/users", test)
+            .get("/posts/", test)
+            .finalize();
+
+        assert_eq!(call(&router, Method::Get, "/users/").0, StatusCode::NotFound);
+        assert_eq!(call(&router, Method::Get, "/posts").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn trailing_slash_redirect() {
+        let router = RouteBuilder::new()
+            .set_trailing_slash(TrailingSlash::Redirect(StatusCode::PermanentRedirect))
+            .get("/users", test)
+            .get("/posts/", test)
+            .get("/files/*rest", test)
+            .post("/users/", test)
+            .finalize();
+
+        let res = call_raw(&router, Method::Get, "/users/?page=2");
+        assert_eq!(res.status(), StatusCode::PermanentRedirect);
+        assert_eq!(location(&res), Some("/users?page=2".to_owned()));
+
+        let res = call_raw(&router, Method::Get, "/posts");
+        assert_eq!(res.status(), StatusCode::PermanentRedirect);
+        assert_eq!(location(&res), Some("/posts/".to_owned()));
+
+        // matching requests, including a wildcard capturing the slash, are not redirected
+        assert_eq!(call(&router, Method::Get, "/users"), (StatusCode::Ok, "/users".to_owned()));
+        assert_eq!(
+            call(&router, Method::Get, "/files/a/"),
+            (StatusCode::Ok, "/files/a/".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Post, "/users/"),
+            (StatusCode::Ok, "/users/".to_owned())
+        );
+        assert_eq!(call(&router, Method::Post, "/users").0, StatusCode::PermanentRedirect);
+        assert_eq!(call(&router, Method::Get, "/other/").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn trailing_slash_match() {
+        let router = RouteBuilder::new()
+            .set_trailing_slash(TrailingSlash::Match)
+            .get("/users/:id", echo_params)
+            .get("/posts/", echo_params)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/users/13/"),
+            (StatusCode::Ok, ":id=13".to_owned())
+        );
+        assert_eq!(call(&router, Method::Get, "/posts"), (StatusCode::Ok, "".to_owned()));
+        assert_eq!(
+            call(&router, Method::Get, "/users/").0,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn clean_path_route() {
+        let router = RouteBuilder::new()
+            .set_clean_path(CleanPath::Route)
+            .get("/users/:id", echo_params)
+            .get("/static/*path", echo_params)
+            .finalize();
+
+        for path in &["/users/5", "//users///5", "/users/./5", "/users/x/../5"] {
+            assert_eq!(
+                call(&router, Method::Get, path),
+                (StatusCode::Ok, ":id=5".to_owned()),
+                "requesting {}",
+                path
+            );
+        }
+        assert_eq!(
+            call(&router, Method::Get, "/static/css/../../../etc/passwd").0,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/static/css/%2e%2e/app.js"),
+            (StatusCode::Ok, "*path=app.js".to_owned())
+        );
+    }
+
+    #[test]
+    fn clean_path_redirect() {
+        let router = RouteBuilder::new()
+            .set_clean_path(CleanPath::Redirect(StatusCode::MovedPermanently))
+            .get("/users/:id", echo_params)
+            .finalize();
+
+        let res = call_raw(&router, Method::Get, "//users/./5?x=1");
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        assert_eq!(location(&res), Some("/users/5?x=1".to_owned()));
+        assert_eq!(
+            call(&router, Method::Get, "/users/5"),
+            (StatusCode::Ok, ":id=5".to_owned())
+        );
+    }
+
+    #[test]
+    fn clean_path_off() {
+        let router = RouteBuilder::new().get("/users/:id", echo_params).finalize();
+        assert_eq!(
+            call(&router, Method::Get, "//users/5").0,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn case_sensitive() {
+        let router = RouteBuilder::new().get("/about", test).finalize();
+        assert_eq!(call(&router, Method::Get, "/About").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let router = RouteBuilder::new()
+            .set_case_sensitivity(CaseSensitivity::Insensitive)
+            .get("/about", test)
+            .get("/users/:name/Posts", echo_params)
+            .finalize();
+
+        assert_eq!(call(&router, Method::Get, "/ABOUT"), (StatusCode::Ok, "/ABOUT".to_owned()));
+        assert_eq!(
+            call(&router, Method::Get, "/Users/Bob/posts"),
+            (StatusCode::Ok, ":name=Bob".to_owned())
+        );
+        assert_eq!(call(&router, Method::Get, "/abouts").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn case_redirect() {
+        let router = RouteBuilder::new()
+            .set_case_sensitivity(CaseSensitivity::Redirect(StatusCode::MovedPermanently))
+            .get("/about/Team", test)
+            .get("/users/:name", test)
+            .finalize();
+
+        let res = call_raw(&router, Method::Get, "/About/team?x=1");
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        assert_eq!(location(&res), Some("/about/Team?x=1".to_owned()));
+
+        let res = call_raw(&router, Method::Get, "/USERS/Bob");
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        assert_eq!(location(&res), Some("/users/Bob".to_owned()));
+
+        assert_eq!(
+            call(&router, Method::Get, "/users/Bob"),
+            (StatusCode::Ok, "/users/Bob".to_owned())
+        );
+    }
+
+    #[test]
+    fn case_and_trailing_slash() {
+        let builder = |case, slash| {
+            RouteBuilder::new()
+                .set_case_sensitivity(case)
+                .set_trailing_slash(slash)
+                .get("/about", test)
+                .finalize()
+        };
+        let moved = StatusCode::MovedPermanently;
+
+        let router = builder(CaseSensitivity::Insensitive, TrailingSlash::Match);
+        assert_eq!(call(&router, Method::Get, "/About/"), (StatusCode::Ok, "/About/".to_owned()));
+
+        let router = builder(CaseSensitivity::Redirect(moved), TrailingSlash::Redirect(moved));
+        let res = call_raw(&router, Method::Get, "/About/?x=1");
+        assert_eq!(res.status(), moved);
+        assert_eq!(location(&res), Some("/about?x=1".to_owned()));
+
+        // each option only corrects the part of the path it redirects for
+        let router = builder(CaseSensitivity::Redirect(moved), TrailingSlash::Match);
+        let res = call_raw(&router, Method::Get, "/About/");
+        assert_eq!(location(&res), Some("/about/".to_owned()));
+        assert_eq!(call(&router, Method::Get, "/about/").0, StatusCode::Ok);
+
+        let router = builder(CaseSensitivity::Insensitive, TrailingSlash::Redirect(moved));
+        let res = call_raw(&router, Method::Get, "/About/");
+        assert_eq!(location(&res), Some("/About".to_owned()));
+        assert_eq!(call(&router, Method::Get, "/About").0, StatusCode::Ok);
+
+        let router = builder(CaseSensitivity::Sensitive, TrailingSlash::Match);
+        assert_eq!(call(&router, Method::Get, "/About/").0, StatusCode::NotFound);
+
+        // other methods are not allowed for any path the options let match
+        let router = builder(CaseSensitivity::Sensitive, TrailingSlash::Redirect(moved));
+        assert_eq!(call(&router, Method::Post, "/about/").0, StatusCode::MethodNotAllowed);
+        let router = builder(CaseSensitivity::Insensitive, TrailingSlash::Strict);
+        assert_eq!(call(&router, Method::Post, "/About").0, StatusCode::MethodNotAllowed);
+        let router = builder(CaseSensitivity::Redirect(moved), TrailingSlash::Match);
+        assert_eq!(call(&router, Method::Post, "/About/").0, StatusCode::MethodNotAllowed);
+        let router = builder(CaseSensitivity::Sensitive, TrailingSlash::Strict);
+        assert_eq!(call(&router, Method::Post, "/about/").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn host_routes() {
+        let router = RouteBuilder::new()
+            .host("*.example.com", |b| b.get("/", echo_params).get("/about", test))
+            .host("api.example.com", |b| b.get("/", test).post("/users", test))
+            .host(":tenant.example.com", |b| b.get("/users/:id", echo_params))
+            .get("/health", test)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "http://API.example.com:8080/"),
+            (StatusCode::Ok, "http://API.example.com:8080/".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "http://acme.example.com/users/5"),
+            (StatusCode::Ok, ":id=5;:tenant=acme".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "http://www.example.com/"),
+            (StatusCode::Ok, "".to_owned())
+        );
+
+        // less specific hosts and then routes without a host are tried when a host has no match
+        assert_eq!(
+            call(&router, Method::Get, "http://api.example.com/about"),
+            (StatusCode::Ok, "http://api.example.com/about".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "http://api.example.com/health").0,
+            StatusCode::Ok
+        );
+        assert_eq!(call(&router, Method::Get, "/health").0, StatusCode::Ok);
+
+        assert_eq!(call(&router, Method::Get, "/about").0, StatusCode::NotFound);
+        assert_eq!(
+            call(&router, Method::Get, "http://example.org/about").0,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            call(&router, Method::Get, "http://api.example.com/users").0,
+            StatusCode::MethodNotAllowed
+        );
+        assert_eq!(call(&router, Method::Get, "/users").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn host_header() {
+        let router = RouteBuilder::new()
+            .host("api.example.com", |b| b.get("/", test))
+            .finalize();
+
+        let mut req = Request::new(Method::Get, "/".parse().unwrap());
+        req.headers_mut()
+            .set(hyper::header::Host::new("api.example.com", Some(80)));
+        let res = router
+            .handler
+            .handle(req, RequestData::default())
+            .wait()
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(call(&router, Method::Get, "/").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn host_case() {
+        let err = RouteBuilder::new()
+            .host("API.example.com", |b| b.get("/", test))
+            .try_host("api.example.com", |b| b.try_add(Method::Get, "/", test))
+            .err()
+            .unwrap()
+            .1;
+        match err {
+            RouteError::Duplicate { pattern, .. } => assert_eq!(pattern, "/"),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let router = RouteBuilder::new()
+            .host("API.example.com", |b| b.get("/", test))
+            .finalize();
+        assert_eq!(router.routes().next().unwrap().host, Some("api.example.com".to_owned()));
+    }
+
+    #[test]
+    fn invalid_host() {
+        let err = RouteBuilder::new()
+            .try_host("api.*.com", |b| Ok(b.get("/", test)))
+            .err()
+            .unwrap()
+            .1;
+        assert_eq!(err.pattern(), "api.*.com");
+
+        let err = RouteBuilder::new()
+            .try_host(":id.example.com", |b| b.try_add(Method::Get, "/users/:id", test))
+            .err()
+            .unwrap()
+            .1;
+        assert_eq!(
+            err,
+            RouteError::DuplicateParam {
+                pattern: "/users/:id".to_owned(),
+                param: ":id".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn conflicting_hosts() {
+        let err = RouteBuilder::new()
+            .host(":tenant.example.com", |b| b.get("/", test))
+            .host(":org.example.com", |b| b.get("/users", test))
+            .try_finalize()
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            RouteError::ConflictingHost {
+                host: ":org.example.com".to_owned(),
+                existing: ":tenant.example.com".to_owned(),
+            }
+        );
+
+        assert!(
+            RouteBuilder::new()
+                .host(":tenant.example.com", |b| b.get("/", test))
+                .host(":org.example.org", |b| b.get("/", test))
+                .try_finalize()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn groups() {
+        let router = RouteBuilder::new()
+            .with_middleware(Trace("global"))
+            .group("/api/v1/", |g| {
+                g.with_middleware(Trace("api"))
+                    .get("/users/:id", echo_params)
+                    .group("/admin", |g| {
+                        g.with_middlewares(vec![Trace("admin"), Trace("audit")])
+                            .get_with_middleware("/stats", test, vec![Trace("stats")])
+                    })
+                    .get("/posts", test)
+            })
+            .get("/health", test)
+            .finalize();
+
+        let res = call_raw(&router, Method::Get, "/api/v1/admin/stats");
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(trace(&res), Some("stats,audit,admin,api,global".to_owned()));
+
+        // middleware added to a nested group does not leak to the routes following it
+        let res = call_raw(&router, Method::Get, "/api/v1/posts");
+        assert_eq!(trace(&res), Some("api,global".to_owned()));
+        let res = call_raw(&router, Method::Get, "/health");
+        assert_eq!(trace(&res), Some("global".to_owned()));
+
+        assert_eq!(
+            call(&router, Method::Get, "/api/v1/users/5"),
+            (StatusCode::Ok, ":id=5".to_owned())
+        );
+        assert_eq!(call(&router, Method::Get, "/users/5").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn group_errors() {
+        let err = RouteBuilder::new()
+            .try_group("api", |g| Ok(g.get("/users", test)))
+            .err()
+            .unwrap()
+            .1;
+        assert_eq!(err.pattern(), "api");
+
+        let err = RouteBuilder::new()
+            .try_group("/users/:id", |g| g.try_add(Method::Get, "/posts/:id", test))
+            .err()
+            .unwrap()
+            .1;
+        assert_eq!(
+            err,
+            RouteError::DuplicateParam {
+                pattern: "/users/:id/posts/:id".to_owned(),
+                param: ":id".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn mount() {
+        let billing = RouteBuilder::new()
+            .with_middleware(Trace("billing"))
+            .get("/", test)
+            .get("/invoices/:id", echo_params)
+            .post("/invoices", test)
+            .set_not_found(echo_params)
+            .finalize();
+        let router = RouteBuilder::new()
+            .with_middleware(Trace("global"))
+            .get("/billing/status", echo_params)
+            .group("/api", |g| g.mount("/billing/", billing))
+            .get("/billing/:id", echo_params)
+            .finalize();
+
+        let res = call_raw(&router, Method::Get, "/api/billing/invoices/5?x=1");
+        assert_eq!(trace(&res), Some("billing,global".to_owned()));
+        assert_eq!(
+            call(&router, Method::Get, "/api/billing/invoices/5?x=1"),
+            (StatusCode::Ok, ":id=5".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Post, "http://example.com/api/billing/invoices?x=1"),
+            (StatusCode::Ok, "http://example.com/invoices?x=1".to_owned())
+        );
+        assert_eq!(call(&router, Method::Get, "/api/billing"), (StatusCode::Ok, "/".to_owned()));
+        assert_eq!(call(&router, Method::Get, "/api/billing/"), (StatusCode::Ok, "/".to_owned()));
+
+        // the mounted router's own not found and method not allowed responses apply
+        assert_eq!(call(&router, Method::Get, "/api/billing/other"), (StatusCode::Ok, "".to_owned()));
+        assert_eq!(
+            call(&router, Method::Put, "/api/billing/invoices").0,
+            StatusCode::MethodNotAllowed
+        );
+
+        // routes outside of the prefix are unaffected
+        assert_eq!(call(&router, Method::Get, "/billing/status").0, StatusCode::Ok);
+        assert_eq!(
+            call(&router, Method::Get, "/billing/5"),
+            (StatusCode::Ok, ":id=5".to_owned())
+        );
+    }
+
+    #[test]
+    fn mount_redirects() {
+        let moved = StatusCode::PermanentRedirect;
+        let billing = RouteBuilder::new()
+            .set_trailing_slash(TrailingSlash::Redirect(moved))
+            .set_case_sensitivity(CaseSensitivity::Redirect(moved))
+            .set_clean_path(CleanPath::Redirect(moved))
+            .get("/invoices", echo_base)
+            .finalize();
+        let tenants = RouteBuilder::new().mount("/billing", billing).finalize();
+        let router = RouteBuilder::new().mount("/tenants/:path", tenants).finalize();
+
+        let cases = [
+            ("/tenants/acme/billing/invoices/", "/tenants/acme/billing/invoices"),
+            ("/tenants/acme/billing/Invoices?x=1", "/tenants/acme/billing/invoices?x=1"),
+            ("/tenants/acme/billing//invoices", "/tenants/acme/billing/invoices"),
+        ];
+        for &(uri, expected) in cases.iter() {
+            let res = call_raw(&router, Method::Get, uri);
+            assert_eq!(res.status(), moved, "requesting {}", uri);
+            assert_eq!(location(&res), Some(expected.to_owned()), "requesting {}", uri);
+        }
+
+        // the base path is available to the handlers of the mounted router
+        assert_eq!(
+            call(&router, Method::Get, "/tenants/acme/billing/invoices"),
+            (StatusCode::Ok, "/tenants/acme/billing".to_owned())
+        );
+    }
+
+    fn echo_base(_req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+        let body = data.base_path().to_owned();
+        Box::new(futures::future::ok(
+            Response::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body),
+        ))
+    }
+
+    #[test]
+    fn mount_priority() {
+        let inner = RouteBuilder::new().get("/status", echo_params).finalize();
+        let router = RouteBuilder::new()
+            .mount("/billing", inner)
+            .get("/billing/status", test)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/billing/status"),
+            (StatusCode::Ok, "/billing/status".to_owned())
+        );
+    }
+
+    #[test]
+    fn url_for() {
+        let router = RouteBuilder::new()
+            .get_named("home", "/", test)
+            .get_named("user", "/users/:id(\\d+)", test)
+            .add_named(Method::Post, "user", "/users/:id(\\d+)", test)
+            .get_named("file", "/files/:name.:ext", test)
+            .get_named("archive", "/archive/:year?/:month?", test)
+            .group("/repos", |g| g.get_named("blob", "/*path/blob", test))
+            .get_named("static", "/static/*", test)
+            .get_named("person", "/people/:name([a-z ]+)", test)
+            .finalize();
+
+        let url = |name: &str, params: &[(&str, &str)]| router.url_for(name, params);
+        assert_eq!(url("home", &[]), Ok("/".to_owned()));
+        assert_eq!(url("user", &[("id", "42")]), Ok("/users/42".to_owned()));
+        assert_eq!(
+            url("file", &[("name", "my report"), ("ext", "pdf")]),
+            Ok("/files/my%20report.pdf".to_owned())
+        );
+        assert_eq!(url("archive", &[]), Ok("/archive".to_owned()));
+        assert_eq!(url("archive", &[("year", "2018")]), Ok("/archive/2018".to_owned()));
+        assert_eq!(
+            url("archive", &[("month", "05"), ("year", "2018")]),
+            Ok("/archive/2018/05".to_owned())
+        );
+        assert_eq!(
+            url("blob", &[("path", "lars/src/a b%.rs")]),
+            Ok("/repos/lars/src/a%20b%25.rs/blob".to_owned())
+        );
+        assert_eq!(url("static", &[("", "css/app.css")]), Ok("/static/css/app.css".to_owned()));
+        assert_eq!(url("person", &[("name", "john doe")]), Ok("/people/john%20doe".to_owned()));
+
+        // the built urls are routed back to the route with the given values
+        assert_eq!(
+            call(&router, Method::Get, &url("file", &[("name", "a/b?"), ("ext", "txt")]).unwrap()),
+            (StatusCode::Ok, "/files/a%2Fb%3F.txt".to_owned())
+        );
+    }
+
+    #[test]
+    fn url_for_errors() {
+        let router = RouteBuilder::new()
+            .get_named("user", "/users/:id(\\d+)", test)
+            .get_named("archive", "/archive/:year?/:month?", test)
+            .get_named("static", "/static/*path", test)
+            .finalize();
+
+        assert_eq!(
+            router.url_for("other", &[]),
+            Err(UrlError::UnknownRoute("other".to_owned()))
+        );
+        assert_eq!(
+            router.url_for("user", &[]),
+            Err(UrlError::MissingParam {
+                route: "user".to_owned(),
+                param: "id".to_owned(),
+            })
+        );
+        assert_eq!(
+            router.url_for("static", &[]),
+            Err(UrlError::MissingParam {
+                route: "static".to_owned(),
+                param: "path".to_owned(),
+            })
+        );
+        assert_eq!(
+            router.url_for("archive", &[("month", "05")]),
+            Err(UrlError::MissingParam {
+                route: "archive".to_owned(),
+                param: "year".to_owned(),
+            })
+        );
+        assert_eq!(
+            router.url_for("user", &[("id", "bob")]),
+            Err(UrlError::InvalidParam {
+                route: "user".to_owned(),
+                param: "id".to_owned(),
+                value: "bob".to_owned(),
+            })
+        );
+        assert_eq!(
+            router.url_for("user", &[("id", "42"), ("name", "bob")]),
+            Err(UrlError::UnknownParam {
+                route: "user".to_owned(),
+                param: "name".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn duplicate_route_name() {
+        let err = RouteBuilder::new()
+            .get_named("user", "/users/:id", test)
+            .try_add_named(Method::Get, "user", "/people/:id", test)
+            .err()
+            .unwrap()
+            .1;
+        assert_eq!(
+            err,
+            RouteError::DuplicateName {
+                name: "user".to_owned(),
+                pattern: "/people/:id".to_owned(),
+                route: "/users/:id".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn routes() {
+        let router = RouteBuilder::new()
+            .with_middleware(Trace("global"))
+            .get_named("home", "/", test)
+            .get("/archive/:year?/:month?", test)
+            .group("/api", |g| {
+                g.with_middleware(Trace("api"))
+                    .post_with_middleware("/users", test, vec![Trace("users")])
+            })
+            .host("api.example.com", |b| b.get("/", test))
+            .finalize();
+
+        let routes: Vec<(Option<&str>, String, &str, Option<&str>, usize)> = router
+            .routes()
+            .map(|r| {
+                (
+                    r.host.as_deref(),
+                    r.method.to_string(),
+                    r.pattern.as_str(),
+                    r.name.as_deref(),
+                    r.middleware,
+                )
+            })
+            .collect();
+        assert_eq!(
+            routes,
+            vec![
+                (None, "GET".to_owned(), "/", Some("home"), 0),
+                (None, "POST".to_owned(), "/api/users", None, 2),
+                (None, "GET".to_owned(), "/archive/:year?/:month?", None, 0),
+                (Some("api.example.com"), "GET".to_owned(), "/", None, 0),
+            ]
+        );
+        assert_eq!(router.routes().next().unwrap().location.unwrap().file(), file!());
+    }
+
+    #[test]
+    fn to_dot() {
+        let router = RouteBuilder::new()
+            .get("/users/:id(\\d+)", test)
+            .post("/users/:id(\\d+)", test)
+            .get("/users/new", test)
+            .get("/files/*path", test)
+            .host("api.example.com", |b| b.get("/", test))
+            .finalize();
+
+        let expected = r#"digraph routes {
+    node [shape=box];
+    n0 [label="/"];
+    n1 [label="files"];
+    n2 [label="*path\n[GET]", style=dotted];
+    n1 -> n2;
+    n0 -> n1;
+    n3 [label="users"];
+    n4 [label="new\n[GET]"];
+    n3 -> n4;
+    n5 [label=":id(\\d+)\n[GET, POST]", style=dashed];
+    n3 -> n5;
+    n0 -> n3;
+    n6 [label="api.example.com/"];
+    n7 [label="\n[GET]"];
+    n6 -> n7;
+}
+"#;
+        assert_eq!(router.to_dot(), expected);
+    }
+
+    #[test]
+    fn to_json() {
+        let router = RouteBuilder::new()
+            .get("/", test)
+            .get("/files/:name.:ext", test)
+            .delete("/files/:name.:ext", test)
+            .host(":tenant.example.com", |b| b.get("/", test))
+            .finalize();
+
+        let tree: serde_json::Value = serde_json::from_str(&router.to_json()).unwrap();
+        assert_eq!(
+            tree,
+            json!({
+                "routes": {
+                    "segment": "/",
+                    "kind": "root",
+                    "methods": [],
+                    "children": [
+                        {"segment": "", "kind": "static", "methods": ["GET"], "children": []},
+                        {
+                            "segment": "files",
+                            "kind": "static",
+                            "methods": [],
+                            "children": [{
+                                "segment": ":name.:ext",
+                                "kind": "pattern",
+                                "methods": ["DELETE", "GET"],
+                                "children": [],
+                            }],
+                        },
+                    ],
+                },
+                "hosts": [{
+                    "host": ":tenant.example.com",
+                    "routes": {
+                        "segment": "/",
+                        "kind": "root",
+                        "methods": [],
+                        "children": [
+                            {"segment": "", "kind": "static", "methods": ["GET"], "children": []},
+                        ],
+                    },
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn explain() {
+        let router = RouteBuilder::new()
+            .get("/users/new", test)
+            .get("/users/:id(\\d+)", test)
+            .post("/users/:name", test)
+            .host("api.example.com", |b| b.get("/users/me", test))
+            .finalize();
+
+        let explanation = router.explain(Method::Get, "/users/5");
+        assert_eq!(explanation.route.unwrap().pattern, "/users/:id(\\d+)");
+        assert_eq!(explanation.allowed, vec![Method::Post]);
+        let steps: Vec<(usize, NodeKind, &str, &str, Outcome)> = explanation.searches[0]
+            .steps
+            .iter()
+            .map(|s| (s.depth, s.kind, s.segment.as_str(), s.value.as_str(), s.outcome))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (0, NodeKind::Static, "users", "users", Outcome::Matched),
+                (1, NodeKind::Param, ":id(\\d+)", "5", Outcome::Found),
+            ]
+        );
+
+        let explanation = router.explain(Method::Get, "http://API.example.com/users/bob");
+        assert_eq!(explanation.route, None);
+        assert_eq!(
+            explanation.to_string(),
+            "GET /users/bob
+routes of host 'api.example.com':
+  static 'users' matched 'users'
+routes without a host:
+  static 'users' matched 'users'
+    param ':id(\\d+)' rejected 'bob'
+no route found
+other methods matching: POST
+"
+        );
+    }
+
+    #[test]
+    fn explain_path() {
+        let router = RouteBuilder::new()
+            .set_explain_path("/_explain")
+            .get("/users/:id", test)
+            .host("api.example.com", |b| b.delete("/users/:id", test))
+            .finalize();
+
+        let (status, body) = call(
+            &router,
+            Method::Get,
+            "/_explain?method=DELETE&path=%2Fusers%2F5&host=api.example.com",
+        );
+        assert_eq!(status, StatusCode::Ok);
+        assert!(body.starts_with("DELETE /users/5\n"), "{}", body);
+        assert!(body.contains("handled by DELETE /users/:id"), "{}", body);
+
+        let (status, body) = call(&router, Method::Get, "/_explain?path=/users/5");
+        assert_eq!(status, StatusCode::Ok);
+        assert!(body.contains("handled by GET /users/:id"), "{}", body);
+
+        assert_eq!(call(&router, Method::Get, "/_explain").0, StatusCode::BadRequest);
+        let without = RouteBuilder::new().get("/users/:id", test).finalize();
+        assert_eq!(call(&without, Method::Get, "/_explain?path=/").0, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn swappable_router() {
+        let tagged = |tag: &'static str| {
+            move |_req: Request, _data: RequestData| -> Box<Future<Item = Response, Error = Error>> {
+                Box::new(futures::future::ok(Response::new().with_body(tag)))
+            }
+        };
+        let body = |res: Response| {
+            let body = res.body().concat2().wait().unwrap();
+            str::from_utf8(&body).unwrap().to_owned()
+        };
+        let get = |uri: &str| Request::new(Method::Get, uri.parse().unwrap());
+
+        let routes = SwappableRouter::new(RouteBuilder::new().get("/", tagged("old")).finalize());
+        let service = routes.new_service().unwrap();
+        let in_flight = service.call(get("/"));
+
+        let old = routes.swap(
+            RouteBuilder::new()
+                .get("/", tagged("new"))
+                .get("/beta", tagged("beta"))
+                .finalize(),
+        );
+        assert_eq!(old.routes().count(), 1);
+        assert_eq!(routes.current().routes().count(), 2);
+
+        assert_eq!(body(in_flight.wait().unwrap()), "old");
+        assert_eq!(body(service.call(get("/")).wait().unwrap()), "new");
+        assert_eq!(body(service.call(get("/beta")).wait().unwrap()), "beta");
+        let service = routes.new_service().unwrap();
+        assert_eq!(body(service.call(get("/beta")).wait().unwrap()), "beta");
+    }
+
+    // calls the router with the given headers, returning the status and body.
+    fn call_with(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        headers: &[(&'static str, &'static str)],
+    ) -> (StatusCode, String) {
+        let mut req = Request::new(method, uri.parse().unwrap());
+        for &(name, value) in headers {
+            req.headers_mut().append_raw(name, value);
+        }
+        let res = router.handler.handle(req, RequestData::default()).wait().unwrap();
+        let status = res.status();
+        let body = res.body().concat2().wait().unwrap();
+        (status, str::from_utf8(&body).unwrap().to_owned())
+    }
+
+    #[test]
+    fn header_conditions() {
+        let v2 = Conditions::new().accept("application/vnd.api.v2+json");
+        let json = Conditions::new().content_type("application/json");
+        let router = RouteBuilder::new()
+            .get("/users/:id", echo_params)
+            .when(v2.clone(), |b| b.get("/users/:id", test))
+            .when(Conditions::new().header("X-API-Version", "3"), |b| {
+                b.get("/users/:id", test).get("/v3/:id", echo_params)
+            })
+            .when(json, |b| {
+                b.post("/users", test)
+                    .when(v2, |b| b.put("/users/:id", echo_params))
+            })
+            .finalize();
+
+        let v2_accept = [("Accept", "application/vnd.api.v2+json")];
+        assert_eq!(call_with(&router, Method::Get, "/users/5", &v2_accept).1, "/users/5");
+        let v3 = [("X-API-Version", "3")];
+        assert_eq!(call_with(&router, Method::Get, "/users/5", &v3).1, "/users/5");
+        // a request accepting any media type prefers the route without conditions
+        assert_eq!(call_with(&router, Method::Get, "/users/5", &[]).1, ":id=5");
+        let any = [("Accept", "*/*")];
+        assert_eq!(call_with(&router, Method::Get, "/users/5", &any).1, ":id=5");
+        let v1_accept = [("Accept", "application/json")];
+        assert_eq!(call_with(&router, Method::Get, "/users/5", &v1_accept).1, ":id=5");
+        assert_eq!(
+            call_with(&router, Method::Get, "/v3/5", &[]).0,
+            StatusCode::NotAcceptable
+        );
+
+        let json_body = [("Content-Type", "application/json; charset=utf-8")];
+        assert_eq!(call_with(&router, Method::Post, "/users", &json_body).0, StatusCode::Ok);
+        let form = [("Content-Type", "application/x-www-form-urlencoded")];
+        assert_eq!(
+            call_with(&router, Method::Post, "/users", &form).0,
+            StatusCode::UnsupportedMediaType
+        );
+        let put = [
+            ("Content-Type", "application/json"),
+            ("Accept", "application/json"),
+        ];
+        assert_eq!(
+            call_with(&router, Method::Put, "/users/5", &put).0,
+            StatusCode::NotAcceptable
+        );
+        // but is still handled by a route requiring a media type when no other route matches
+        let put = [("Content-Type", "application/json")];
+        assert_eq!(
+            call_with(&router, Method::Put, "/users/5", &put),
+            (StatusCode::Ok, ":id=5".to_owned())
+        );
+
+        let conditions: Vec<String> = router
+            .routes()
+            .filter(|r| r.pattern == "/users/:id" && r.method == Method::Get)
+            .map(|r| r.conditions.to_string())
+            .collect();
+        assert_eq!(
+            conditions,
+            vec![
+                "",
+                "Accept: application/vnd.api.v2+json",
+                "x-api-version: 3",
+            ]
+        );
+        let put = router.routes().find(|r| r.method == Method::Put).unwrap();
+        assert_eq!(
+            put.conditions.to_string(),
+            "Content-Type: application/json, Accept: application/vnd.api.v2+json"
+        );
+    }
+
+    fn echo_query(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+        let mut query: Vec<String> = data
+            .query()
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        query.sort();
+        let body = format!("{} {}", req.path(), query.join(";"));
+        Box::new(futures::future::ok(
+            Response::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body),
+        ))
+    }
+
+    #[test]
+    fn query_conditions() {
+        let router = RouteBuilder::new()
+            .when(Conditions::new().query("type", "user"), |b| {
+                b.get("/search", echo_query)
+            })
+            .when(Conditions::new().query("type", "repo").query_param("q"), |b| {
+                b.get("/search", echo_query)
+            })
+            .when(
+                Conditions::new()
+                    .query_param("v")
+                    .content_type("application/json"),
+                |b| b.post("/legacy", echo_query),
+            )
+            .get("/legacy", echo_query)
+            .finalize();
+
+        assert_eq!(
+            call(&router, Method::Get, "/search?type=user&q=x"),
+            (StatusCode::Ok, "/search type=user".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/search?q=lars+router%21&type=repo"),
+            (StatusCode::Ok, "/search q=lars router!;type=repo".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/search?type=repo").0,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/search?type=org").0,
+            StatusCode::NotFound
+        );
+        assert_eq!(call(&router, Method::Get, "/search").0, StatusCode::NotFound);
+
+        // the query conditions are checked before those on headers
+        assert_eq!(call(&router, Method::Post, "/legacy").0, StatusCode::NotFound);
+        assert_eq!(
+            call(&router, Method::Post, "/legacy?v=2").0,
+            StatusCode::UnsupportedMediaType
+        );
+        let json = [("Content-Type", "application/json")];
+        assert_eq!(
+            call_with(&router, Method::Post, "/legacy?v=2", &json),
+            (StatusCode::Ok, "/legacy v=2".to_owned())
+        );
+        assert_eq!(
+            call(&router, Method::Get, "/legacy?v=2"),
+            (StatusCode::Ok, "/legacy ".to_owned())
+        );
+    }
+
+    #[test]
+    fn duplicate_conditions() {
+        let v2 = || Conditions::new().accept("application/vnd.api.v2+json");
+        let res = RouteBuilder::new()
+            .try_when(v2(), |b| b.try_add(Method::Get, "/users", test))
+            .and_then(|b| b.try_when(v2(), |b| b.try_add(Method::Get, "/users", test)));
+        match res {
+            Err((_, RouteError::Duplicate { .. })) => {}
+            r => panic!("unexpected result {:?}", r.err().map(|e| e.1)),
+        }
+
+        // the same conditions given in another order are a duplicate too
+        let json = Conditions::new().content_type("application/json");
+        let res = RouteBuilder::new()
+            .try_when(json.clone().accept("text/csv"), |b| {
+                b.try_add(Method::Post, "/users", test)
+            })
+            .and_then(|b| {
+                b.try_when(Conditions::new().accept("text/csv"), |b| {
+                    b.try_when(json, |b| b.try_add(Method::Post, "/users", test))
+                })
+            });
+        match res {
+            Err((_, RouteError::Duplicate { .. })) => {}
+            r => panic!("unexpected result {:?}", r.err().map(|e| e.1)),
+        }
+    }
+
+    #[test]
+    fn condition_status() {
+        let json = || Conditions::new().content_type("application/json");
+        let csv = || Conditions::new().accept("text/csv");
+        let status = |router: &Router| {
+            let headers = [("Content-Type", "text/plain"), ("Accept", "application/json")];
+            call_with(router, Method::Post, "/users", &headers).0
+        };
+
+        // 415 is answered whatever the order the routes were registered in
+        let router = RouteBuilder::new()
+            .when(json(), |b| b.post("/users", test))
+            .when(csv(), |b| b.post("/users", test))
+            .finalize();
+        assert_eq!(status(&router), StatusCode::UnsupportedMediaType);
+        let router = RouteBuilder::new()
+            .when(csv(), |b| b.post("/users", test))
+            .when(json(), |b| b.post("/users", test))
+            .finalize();
+        assert_eq!(status(&router), StatusCode::UnsupportedMediaType);
+
+        // as is 406 over a route the request's query did not satisfy
+        let q = || Conditions::new().query_param("q");
+        for &(ref first, ref second) in &[(q(), csv()), (csv(), q())] {
+            let router = RouteBuilder::new()
+                .when(first.clone(), |b| b.post("/users", test))
+                .when(second.clone(), |b| b.post("/users", test))
+                .finalize();
+            assert_eq!(status(&router), StatusCode::NotAcceptable);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_duplicate_route() {
+        RouteBuilder::new()
+            .get("/test/:id", test)
+            .get("/test/:id", test);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_required_after_optional_path() {
+        RouteBuilder::new().get("/archive/:year?/posts", test);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_duplicate_optional_param_names_path() {
+        RouteBuilder::new().get("/archive/:id/:id?", test);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_duplicate_constrained_param_names_path() {
+        RouteBuilder::new().get("/test/:id(\\d+)/handler/:id", test);
+    }
+
     #[test]
     fn paths() {
         let (tx, rx) = oneshot::channel::<bool>();
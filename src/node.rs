@@ -3,10 +3,19 @@ extern crate hyper;
 use futures::Future;
 
 use hyper::Error;
-use hyper::{Request, Response};
+use hyper::{Method, Request, Response, StatusCode};
+
+use conditions::Conditions;
+use error::RouteError;
+use percent_encoding::percent_decode;
+
+use param::{parse_segment, split_wildcard, Constraint, ParamError, Piece, SegmentPattern};
 
 use std::collections::HashMap;
 use std::fmt;
+use std::panic::Location;
+use std::str::FromStr;
+use std::sync::Arc;
 
 /// Handler is the trait that routes must comply with.
 pub trait Handler: 'static + Send + Sync {
@@ -41,14 +50,127 @@ where
 }
 
 pub struct Match<'a> {
-    pub handler: &'a Box<Handler>,
     pub params: RequestData<'a>,
+    /// The information of the route matched.
+    pub info: &'a RouteInfo,
+    // every route sharing the method and path matched, `info` being the first's
+    routes: &'a [Route],
+    // static segments matched in a differing case, as the length of the path remaining from the
+    // start of the segment along with the registered segment.
+    corrected: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Match<'a> {
+    /// Returns the first route matched whose conditions the request satisfies, or the status to
+    /// respond with if it satisfies none: 415 when a route rejected its content type, otherwise
+    /// 406 when a route rejected it for another header, and 404 when it satisfies the query
+    /// conditions of no route; regardless of the order the routes were registered.
+    ///
+    /// Routes whose required media types the `Accept` header of the request lists explicitly are
+    /// tried first, so that a request accepting them only through a range or by having no
+    /// `Accept` header is handled by a route without such conditions if any.
+    pub fn select(&self, req: &Request) -> Result<&'a Route, StatusCode> {
+        let explicit = self.routes.iter().find(|r| r.info.conditions.check(req, true).is_ok());
+        if let Some(route) = explicit {
+            return Ok(route);
+        }
+
+        let rank = |status: StatusCode| match status {
+            StatusCode::UnsupportedMediaType => 2,
+            StatusCode::NotAcceptable => 1,
+            _ => 0,
+        };
+        let mut status = StatusCode::NotFound;
+        for route in self.routes {
+            match route.info.conditions.check(req, false) {
+                Ok(()) => return Ok(route),
+                Err(s) => {
+                    if rank(s) > rank(status) {
+                        status = s;
+                    }
+                }
+            }
+        }
+        Err(status)
+    }
+
+    /// Returns the given path, which must be the one this match was found for, with the static
+    /// segments matched in a differing case replaced by their registered case; or `None` when
+    /// every segment was matched in its registered case.
+    pub fn canonical_path(&self, path: &str) -> Option<String> {
+        if self.corrected.is_empty() {
+            return None;
+        }
+        let mut canonical = path.to_owned();
+        for &(remaining, segment) in &self.corrected {
+            let start = path.len() - remaining;
+            canonical.replace_range(start..start + segment.len(), segment);
+        }
+        Some(canonical)
+    }
+
+    pub fn with_param(mut self, id: &'a str, value: &str) -> Self {
+        self.params
+            .params
+            .get_or_insert_with(HashMap::new)
+            .insert(id, decode(id, value));
+        self.params
+            .raw_params
+            .get_or_insert_with(HashMap::new)
+            .insert(id, value.to_owned());
+        self
+    }
+}
+
+// percent-decodes a captured value. A wildcard keeps encoded slashes `%2F` as they are, so that
+// every `/` in its decoded value separates segments of the request path, along with encoded
+// percent signs `%25` so that `%2F` in its decoded value always stands for an encoded slash,
+// whereas a param is decoded fully as it only ever captures a single segment. A value which does
+// not decode to valid UTF-8 is kept raw.
+fn decode(id: &str, value: &str) -> String {
+    if !value.contains('%') {
+        return value.to_owned();
+    }
+    if !id.starts_with('*') {
+        return decode_utf8(value).unwrap_or_else(|| value.to_owned());
+    }
+
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    loop {
+        let kept = rest.as_bytes().windows(3).position(|w| {
+            w[0] == b'%' && (w[1..].eq_ignore_ascii_case(b"2F") || &w[1..] == b"25")
+        });
+        let end = kept.unwrap_or(rest.len());
+        match decode_utf8(&rest[..end]) {
+            Some(part) => decoded.push_str(&part),
+            None => return value.to_owned(),
+        }
+        if kept.is_none() {
+            return decoded;
+        }
+        decoded.push_str(&rest[end..end + 3]);
+        rest = &rest[end + 3..];
+    }
+}
+
+fn decode_utf8(value: &str) -> Option<String> {
+    percent_decode(value.as_bytes())
+        .decode_utf8()
+        .ok()
+        .map(|v| v.into_owned())
 }
 
 /// RequestData contains request scoped information.
 ///
 /// The RequestData is passed down to your handler for consumption.
 ///
+/// Captured parameters are percent-decoded, eg. `/users/John%20Doe` captures `John Doe` for
+/// `/users/:name`, and their constraints and types are checked against the decoded value.
+/// Wildcards keep encoded slashes `%2F` and percent signs `%25` as is, so they cannot be confused
+/// with the separators of the captured path. A value which does not decode to valid UTF-8 is kept
+/// raw, and the undecoded values are always available using `RequestData::raw_param`.
+///
 /// # Examples
 ///
 /// ```
@@ -76,183 +198,1249 @@ pub struct Match<'a> {
 /// }
 /// ```
 ///
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct RequestData<'p> {
+    /// The captured parameters, percent-decoded, keyed by their name including the leading `:`
+    /// or `*`.
     pub params: Option<HashMap<&'p str, String>>,
+    // the captured parameters exactly as they appeared in the request path
+    pub(crate) raw_params: Option<HashMap<&'p str, String>>,
+    // the query parameters required by the conditions of the route, percent-decoded, by name
+    pub(crate) query: Option<HashMap<String, String>>,
+    // the path prefix the router is mounted under, empty when it is not mounted
+    pub(crate) base: String,
 }
 
-#[derive(Debug)]
+impl<'p> RequestData<'p> {
+    /// Returns the named parameter converted into the requested type.
+    ///
+    /// The name is given without its leading `:` or `*`, and the conversion is done using `FromStr`
+    /// so any type implementing it may be requested, not only the built-in route converters.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let router = RouteBuilder::new()
+    ///     .get("/users/:id<u64>", user)
+    ///     .finalize();
+    ///
+    /// fn user(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    let id: u64 = match data.param("id") {
+    ///        Ok(id) => id,
+    ///        Err(e) => {
+    ///            return Box::new(future::ok(
+    ///                Response::new()
+    ///                    .with_status(StatusCode::BadRequest)
+    ///                    .with_body(e.to_string()),
+    ///            ))
+    ///        }
+    ///    };
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body(format!("user {}", id)),
+    ///    ))
+    /// }
+    /// ```
+    pub fn param<T>(&self, name: &str) -> Result<T, ParamError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = lookup(&self.params, name).ok_or_else(|| ParamError::Missing(name.to_owned()))?;
+
+        value.parse::<T>().map_err(|e| ParamError::Invalid {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Returns the named parameter exactly as it appeared in the request path, before any
+    /// percent-decoding.
+    ///
+    /// The name is given without its leading `:` or `*`.
+    pub fn raw_param(&self, name: &str) -> Option<&str> {
+        lookup(&self.raw_params, name)
+    }
+
+    /// Returns the captured parameters exactly as they appeared in the request path, keyed by
+    /// their name including the leading `:` or `*`.
+    pub fn raw_params(&self) -> Option<&HashMap<&'p str, String>> {
+        self.raw_params.as_ref()
+    }
+
+    /// Returns the value of the query parameter required by the conditions of the route.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_ref()
+            .and_then(|query| query.get(name))
+            .map(|v| v.as_str())
+    }
+
+    /// Returns the query parameters required by the conditions of the route, percent-decoded,
+    /// keyed by their name, see `Conditions::query`.
+    pub fn query(&self) -> Option<&HashMap<String, String>> {
+        self.query.as_ref()
+    }
+
+    /// Returns the path prefix, as requested, the router handling the request is mounted under,
+    /// eg. `/billing` for `/billing/invoices/5`; or an empty string when it is not mounted, see
+    /// `RouteBuilder::mount`.
+    pub fn base_path(&self) -> &str {
+        &self.base
+    }
+}
+
+// finds the parameter by name, ignoring the leading `:` or `*` of the keys.
+fn lookup<'a>(params: &'a Option<HashMap<&str, String>>, name: &str) -> Option<&'a str> {
+    params.as_ref().and_then(|params| {
+        params
+            .iter()
+            .find(|&(k, _)| &k[1..] == name)
+            .map(|(_, v)| v.as_str())
+    })
+}
+
+/// RouteInfo describes how a route was registered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo {
+    pub method: Method,
+    /// The path pattern, including the prefix of the groups it was registered within.
+    pub pattern: String,
+    /// The name given to the route, if any.
+    pub name: Option<String>,
+    /// The host pattern the route is restricted to, if any.
+    pub host: Option<String>,
+    /// The number of middleware wrapping the route, including those of its groups but not the
+    /// global middleware.
+    pub middleware: usize,
+    /// The location of the call which registered the route, when known.
+    pub location: Option<&'static Location<'static>>,
+    /// The conditions on the headers or query of requests the route handles, see
+    /// `RouteBuilder::when`.
+    pub conditions: Conditions,
+}
+
+impl RouteInfo {
+    /// Creates the information of a route for the method and pattern, without name, host,
+    /// middleware nor conditions.
+    pub fn new(
+        method: Method,
+        pattern: &str,
+        location: Option<&'static Location<'static>>,
+    ) -> RouteInfo {
+        RouteInfo {
+            method,
+            pattern: pattern.to_owned(),
+            name: None,
+            host: None,
+            middleware: 0,
+            location,
+            conditions: Conditions::new(),
+        }
+    }
+}
+
+/// Route is a registered handler along with the information describing it.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub handler: Arc<Box<Handler>>,
+    pub info: RouteInfo,
+}
+
+/// NodeKind is the kind of a child node, in the order of priority children are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeKind {
+    Static,
+    Pattern,
+    Param,
+    Wildcard,
+}
+
+impl NodeKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            NodeKind::Static => "static",
+            NodeKind::Pattern => "pattern",
+            NodeKind::Param => "param",
+            NodeKind::Wildcard => "wildcard",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct NodeExtra {
     node: Box<Node>,
     id: String,
+    constraint: Option<Constraint>,
+    // static text following a wildcard within its segment eg. `.map` for `*file.map`
+    suffix: String,
+    // the segment and route which first registered this child, used when reporting conflicts
+    segment: String,
+    route: String,
+}
+
+impl NodeExtra {
+    fn accepts(&self, value: &str) -> bool {
+        match self.constraint {
+            Some(ref c) => c.accepts_segment(value),
+            None => true,
+        }
+    }
+
+    // returns the wildcard value captured from the given text, which must end with the suffix
+    // and not be empty once it's removed.
+    fn strip_suffix<'a>(&self, captured: &'a str) -> Option<&'a str> {
+        if captured.len() > self.suffix.len() && captured.ends_with(self.suffix.as_str()) {
+            return Some(&captured[..captured.len() - self.suffix.len()]);
+        }
+        None
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct PatternExtra {
+    node: Box<Node>,
+    pattern: SegmentPattern,
+    segment: String,
+    route: String,
+}
+
+#[derive(Debug, Clone)]
 pub struct Node {
     statics: Option<HashMap<String, Node>>,
-    param: Option<NodeExtra>,
-    wild: Option<NodeExtra>,
-    handler: Option<Box<Handler>>,
+    patterns: Vec<PatternExtra>,
+    params: Vec<NodeExtra>,
+    wilds: Vec<NodeExtra>,
+    // the routes registered on this node, those with conditions first in the order they were
+    // registered, followed by the one without if any
+    routes: Vec<Route>,
 }
 
 impl Node {
     pub fn new() -> Node {
         return Node {
             statics: None,
-            param: None,
-            wild: None,
-            handler: None,
+            patterns: Vec::new(),
+            params: Vec::new(),
+            wilds: Vec::new(),
+            routes: Vec::new(),
         };
     }
 
-    pub fn add(&mut self, path: &str, handler: Box<Handler>) {
-        let parts: Vec<&str> = path.splitn(2, '/').collect();
-        let char = parts[0].chars().nth(0);
+    // returns the param child for the given segment, creating it if necessary. Sibling params
+    // must differ by constraint, those sharing one must also share their name.
+    fn param_child(
+        &mut self,
+        id: String,
+        constraint: Option<Constraint>,
+        segment: &str,
+        route: &str,
+    ) -> Result<&mut Node, RouteError> {
+        let mut index = None;
+        for (i, p) in self.params.iter().enumerate() {
+            let same_constraint = match (p.constraint.as_ref(), constraint.as_ref()) {
+                (Some(a), Some(b)) => a.same_as(b),
+                (None, None) => true,
+                _ => false,
+            };
+            if !same_constraint {
+                continue;
+            }
+            if p.id != id {
+                return Err(RouteError::Conflict {
+                    pattern: route.to_owned(),
+                    segment: segment.to_owned(),
+                    existing: p.segment.clone(),
+                    route: p.route.clone(),
+                });
+            }
+            index = Some(i);
+            break;
+        }
 
-        if char == Some(':') {
-            if self.param.is_none() {
-                self.param = Some(NodeExtra {
+        let index = match index {
+            Some(i) => i,
+            None => {
+                self.params.push(NodeExtra {
                     node: Box::new(Node::new()),
-                    id: parts[0].to_owned(),
+                    id,
+                    constraint,
+                    suffix: String::new(),
+                    segment: segment.to_owned(),
+                    route: route.to_owned(),
                 });
-            } else {
-                let id = &self.param.as_ref().unwrap().id;
-                if id != parts[0] {
-                    panic!(
-                        "conflicting parameter id's detected '{}' and '{}' in remaining path '{}'",
-                        id, parts[0], path
-                    );
+                self.params.len() - 1
+            }
+        };
+        Ok(self.params[index].node.as_mut())
+    }
+
+    // returns the pattern child for the given segment, creating it if necessary. Sibling patterns
+    // matching the same segments must also share their parameter names.
+    fn pattern_child(
+        &mut self,
+        pattern: SegmentPattern,
+        segment: &str,
+        route: &str,
+    ) -> Result<&mut Node, RouteError> {
+        let mut index = None;
+        for (i, p) in self.patterns.iter().enumerate() {
+            match p.pattern.compare(&pattern) {
+                Some(true) => {
+                    index = Some(i);
+                    break;
                 }
+                Some(false) => {
+                    return Err(RouteError::Conflict {
+                        pattern: route.to_owned(),
+                        segment: segment.to_owned(),
+                        existing: p.segment.clone(),
+                        route: p.route.clone(),
+                    })
+                }
+                None => {}
             }
-            let node = self.param.as_mut().unwrap().node.as_mut();
-            if parts.len() == 1 {
-                node.handler = Some(handler);
-                return;
+        }
+
+        let index = match index {
+            Some(i) => i,
+            None => {
+                self.patterns.push(PatternExtra {
+                    node: Box::new(Node::new()),
+                    pattern,
+                    segment: segment.to_owned(),
+                    route: route.to_owned(),
+                });
+                self.patterns.len() - 1
             }
-            node.add(parts[1], handler)
-        } else if char == Some('*') {
-            // check is the rest of path has another wildcard or param, as that's not permitted.
-            if parts.len() > 1 && (parts[1].contains("*") || parts[1].contains(":")) {
-                panic!("no wildcard '*' nor param ':' is permitted after the first wildcard param, remaining path '{}'", path);
+        };
+        Ok(self.patterns[index].node.as_mut())
+    }
+
+    // returns the wildcard child for the given segment, creating it if necessary. Sibling wildcards
+    // must differ by suffix, those sharing one must also share their name.
+    fn wild_child(&mut self, segment: &str, route: &str) -> Result<&mut Node, RouteError> {
+        let (id, suffix) = split_wildcard(segment);
+
+        let mut index = None;
+        for (i, w) in self.wilds.iter().enumerate() {
+            if w.suffix != suffix {
+                continue;
+            }
+            if w.id != id {
+                return Err(RouteError::Conflict {
+                    pattern: route.to_owned(),
+                    segment: segment.to_owned(),
+                    existing: w.segment.clone(),
+                    route: w.route.clone(),
+                });
             }
+            index = Some(i);
+            break;
+        }
 
-            if self.wild.is_none() {
-                self.wild = Some(NodeExtra {
+        let index = match index {
+            Some(i) => i,
+            None => {
+                self.wilds.push(NodeExtra {
                     node: Box::new(Node::new()),
-                    id: parts[0].to_owned(),
+                    id: id.to_owned(),
+                    constraint: None,
+                    suffix: suffix.to_owned(),
+                    segment: segment.to_owned(),
+                    route: route.to_owned(),
                 });
-            } else {
-                let id = &self.wild.as_ref().unwrap().id;
-                if id != parts[0] {
-                    panic!(
-                        "conflicting wildcard id's detected '{}' and '{}' in remaining path '{}'",
-                        id, parts[0], path
-                    );
-                }
+                self.wilds.len() - 1
             }
-            self.wild.as_mut().unwrap().node.as_mut().handler = Some(handler);
-            return;
-        } else {
-            if self.statics.is_none() {
-                self.statics = Some(HashMap::new());
+        };
+        Ok(self.wilds[index].node.as_mut())
+    }
+
+    // returns the child for the given segment, creating it if necessary, where `rest` is the
+    // remainder of the path following the segment.
+    fn child(&mut self, segment: &str, rest: &str, route: &str) -> Result<&mut Node, RouteError> {
+        let invalid = |reason: String| RouteError::InvalidSegment {
+            pattern: route.to_owned(),
+            segment: segment.to_owned(),
+            reason,
+        };
+
+        if let Some(name) = segment.strip_prefix('*') {
+            // check is the rest of path has another wildcard or param, as that's not permitted.
+            if name.contains(['*', ':']) || rest.contains(['*', ':']) {
+                return Err(invalid(
+                    "no wildcard '*' nor param ':' is permitted after the first wildcard".to_owned(),
+                ));
             }
+            return self.wild_child(segment, route);
+        }
+
+        let mut pieces = parse_segment(segment).map_err(&invalid)?;
+
+        // segments mixing static text and params are matched as a whole pattern
+        if pieces.len() > 1 {
+            let pattern = SegmentPattern::new(pieces).map_err(&invalid)?;
+            return self.pattern_child(pattern, segment, route);
+        }
+
+        match pieces.pop() {
+            Some(Piece::Param(id, constraint)) => self.param_child(id, constraint, segment, route),
+            _ => Ok(self.statics
+                .get_or_insert_with(HashMap::new)
+                .entry(segment.to_owned())
+                .or_insert_with(Node::new)),
+        }
+    }
 
-            let node = self.statics
-                .as_mut()
-                .unwrap()
-                .entry(parts[0].to_owned())
-                .or_insert(Node::new());
+    /// Adds the route for the given path, relative to this node, returning an error if another
+    /// route was already registered for it with the same conditions unless `replace` is set.
+    pub fn add(&mut self, path: &str, route: Route, replace: bool) -> Result<(), RouteError> {
+        let parts: Vec<&str> = path.splitn(2, '/').collect();
+        let rest = if parts.len() > 1 { parts[1] } else { "" };
+        let node = self.child(parts[0], rest, &route.info.pattern)?;
 
-            if parts.len() == 1 {
-                node.handler = Some(handler);
-                return;
+        if parts.len() > 1 {
+            return node.add(parts[1], route, replace);
+        }
+        let existing = node
+            .routes
+            .iter()
+            .position(|r| r.info.conditions == route.info.conditions);
+        if let Some(i) = existing {
+            if !replace {
+                let existing = &node.routes[i].info;
+                return Err(RouteError::Duplicate {
+                    method: route.info.method,
+                    pattern: route.info.pattern,
+                    location: route.info.location,
+                    route: existing.pattern.clone(),
+                    route_location: existing.location,
+                });
             }
-            node.add(parts[1], handler)
+            node.routes[i] = route;
+            return Ok(());
         }
+        if route.info.conditions.is_empty() {
+            node.routes.push(route);
+        } else {
+            let i = node
+                .routes
+                .iter()
+                .position(|r| r.info.conditions.is_empty())
+                .unwrap_or(node.routes.len());
+            node.routes.insert(i, route);
+        }
+        Ok(())
+    }
+
+    /// Appends the information of every route registered on this node and below it.
+    pub fn routes<'a>(&'a self, routes: &mut Vec<&'a RouteInfo>) {
+        for route in &self.routes {
+            routes.push(&route.info);
+        }
+        for (_, _, node) in self.children() {
+            node.routes(routes);
+        }
+    }
+
+    /// Returns the information of the first route registered on this node, if any.
+    pub fn route(&self) -> Option<&RouteInfo> {
+        self.routes.first().map(|r| &r.info)
     }
 
+    /// Returns the children of this node in the order they are tried, along with their kind and
+    /// the segment they were registered with, eg. `users`, `:name.:ext`, `:id(\d+)` or `*path`.
+    /// Static children are ordered by segment.
+    pub fn children(&self) -> Vec<(NodeKind, &str, &Node)> {
+        let mut children = Vec::new();
+        if let Some(ref statics) = self.statics {
+            for (segment, node) in statics {
+                children.push((NodeKind::Static, segment.as_str(), node));
+            }
+            children.sort_by_key(|c| c.1);
+        }
+        for p in &self.patterns {
+            children.push((NodeKind::Pattern, p.segment.as_str(), p.node.as_ref()));
+        }
+        for enode in &self.params {
+            children.push((NodeKind::Param, enode.segment.as_str(), enode.node.as_ref()));
+        }
+        for enode in &self.wilds {
+            children.push((NodeKind::Wildcard, enode.segment.as_str(), enode.node.as_ref()));
+        }
+        children
+    }
+
+    // returns a match for the routes registered on this node, if any.
+    fn matched(&self) -> Option<Match<'_>> {
+        self.routes.first().map(|route| Match {
+            params: RequestData::default(),
+            info: &route.info,
+            routes: &self.routes,
+            corrected: Vec::new(),
+        })
+    }
+
+    /// Finds the handler for the given path, along with any parameters captured along the way.
+    ///
+    /// Children are tried in order of priority, static > pattern > param > wildcard, and when a
+    /// child cannot complete the match the search backtracks and tries the next one; this allows
+    /// overlapping routes such as `/test/new/edit` and `/test/:id/view` to both be reachable.
     pub fn find(&self, path: &str) -> Option<Match> {
-        let parts: Vec<&str> = path.splitn(2, '/').collect();
+        self.search(path, false, 0, &mut ())
+    }
+
+    /// Finds the handler for the given path like `find`, but matching static segments regardless
+    /// of their ASCII case, eg. `/About` matches the route `/about`. Parameters are captured as
+    /// sent.
+    ///
+    /// Use `Match::canonical_path` to retrieve the path in the case it was registered.
+    pub fn find_ignore_case(&self, path: &str) -> Option<Match<'_>> {
+        self.search(path, true, 0, &mut ())
+    }
 
-        if self.statics.is_some() {
-            let inner = self.statics.as_ref();
-            if inner.is_some() {
-                let node = inner.unwrap().get(parts[0]);
+    /// Finds the handler for the given path like `find`, appending each child tried along the way
+    /// to `steps`.
+    pub fn trace(&self, path: &str, steps: &mut Vec<Step>) -> Option<Match<'_>> {
+        self.search(path, false, 0, steps)
+    }
 
-                if node.is_some() {
-                    let node = node.unwrap();
+    fn search<T: Tracer>(
+        &self,
+        path: &str,
+        ignore_case: bool,
+        depth: usize,
+        tracer: &mut T,
+    ) -> Option<Match<'_>> {
+        let parts: Vec<&str> = path.splitn(2, '/').collect();
+        let rest = parts.get(1).cloned();
 
-                    if parts.len() == 1 {
-                        let handler = &node.handler.as_ref()?;
-                        return Some(Match {
-                            handler,
-                            params: RequestData { params: None },
-                        });
+        if let Some(ref statics) = self.statics {
+            // the exact case is tried first, then, ignoring case, any other static segment; ASCII
+            // case changes never change the length, so the registered segment can later be
+            // substituted in place of the requested one.
+            let exact = statics.get_key_value(parts[0]).into_iter();
+            let other = statics.iter().filter(|&(segment, _)| {
+                ignore_case && segment != parts[0] && segment.eq_ignore_ascii_case(parts[0])
+            });
+            for (segment, node) in exact.chain(other) {
+                let child = (NodeKind::Static, segment.as_str());
+                let m = node.enter(child, parts[0], rest, ignore_case, depth, tracer);
+                if let Some(mut m) = m {
+                    if segment != parts[0] {
+                        m.corrected.push((path.len(), segment));
                     }
-                    return node.find(parts[1]);
+                    return Some(m);
                 }
             }
         }
 
-        if self.param.is_some() {
-            let enode = self.param.as_ref().unwrap();
-            let node = enode.node.as_ref();
-
-            if parts.len() == 1 {
-                if path.len() == 0 {
-                    // can return none here as can't have a wildcard
-                    // and a param at the same path
-                    return None;
+        // patterns are more specific than a lone param so are tried first, in the order they were
+        // registered; within the segment only the first way of matching the pattern is considered.
+        for p in &self.patterns {
+            let child = (NodeKind::Pattern, p.segment.as_str());
+            let mut captured = Vec::new();
+            if !p.pattern.matches(parts[0], &mut captured) {
+                tracer.step(depth, child, parts[0], Outcome::Rejected);
+                continue;
+            }
+            let m = p.node.enter(child, parts[0], rest, ignore_case, depth, tracer);
+            if let Some(mut m) = m {
+                for (id, value) in captured {
+                    m = m.with_param(id, value);
                 }
-                let handler = node.handler.as_ref()?;
-                let mut m = Some(Match {
-                    handler,
-                    params: RequestData {
-                        params: Some(HashMap::new()),
-                    },
-                });
-                m.as_mut()
-                    .unwrap()
-                    .params
-                    .params
-                    .as_mut()
-                    .unwrap()
-                    .insert(enode.id.as_ref(), parts[0].to_owned());
-                return m;
+                return Some(m);
+            }
+        }
+
+        // params must capture a non empty segment, satisfying the constraint if any; siblings
+        // are tried in the order they were registered.
+        for enode in &self.params {
+            let child = (NodeKind::Param, enode.segment.as_str());
+            if parts[0].is_empty() || !enode.accepts(parts[0]) {
+                tracer.step(depth, child, parts[0], Outcome::Rejected);
+                continue;
+            }
+            let m = enode.node.enter(child, parts[0], rest, ignore_case, depth, tracer);
+            if let Some(m) = m {
+                return Some(m.with_param(&enode.id, parts[0]));
             }
+        }
 
-            let mut results = node.find(parts[1]);
-            if results.is_some() {
-                let results = results.as_mut().unwrap();
-                if results.params.params.is_none() {
-                    results.params.params = Some(HashMap::new());
+        // a wildcard may coexist with a param at the same position, in which case it
+        // catches anything the param could not, whether deeper or non-matching. Routes continuing
+        // after the wildcard are tried first with the longest capture, giving up a segment at a
+        // time, eg. `*path/blob` captures `a/blob/b` from `a/blob/b/blob`; a route ending with
+        // the wildcard captures everything and is tried last.
+        for enode in &self.wilds {
+            let child = (NodeKind::Wildcard, enode.segment.as_str());
+            // only static segments may follow a wildcard
+            let mut end = if enode.node.statics.is_some() { path.len() } else { 0 };
+            while let Some(i) = path[..end].rfind('/') {
+                end = i;
+                if let Some(value) = enode.strip_suffix(&path[..end]) {
+                    let rest = Some(&path[end + 1..]);
+                    if let Some(m) = enode.node.enter(child, value, rest, ignore_case, depth, tracer) {
+                        return Some(m.with_param(&enode.id, value));
+                    }
+                }
+            }
+            match enode.strip_suffix(path) {
+                Some(value) => {
+                    if let Some(m) = enode.node.enter(child, value, None, ignore_case, depth, tracer) {
+                        return Some(m.with_param(&enode.id, value));
+                    }
                 }
-                let p = results.params.params.as_mut().unwrap();
-                p.insert(enode.id.as_ref(), parts[0].to_owned());
+                None => tracer.step(depth, child, path, Outcome::Rejected),
+            }
+        }
+        None
+    }
+
+    // continues the search on this node, being the child whose segment matched `value`, where
+    // `rest` is the remainder of the path following the segment if any.
+    fn enter<T: Tracer>(
+        &self,
+        child: (NodeKind, &str),
+        value: &str,
+        rest: Option<&str>,
+        ignore_case: bool,
+        depth: usize,
+        tracer: &mut T,
+    ) -> Option<Match<'_>> {
+        match rest {
+            Some(rest) => {
+                tracer.step(depth, child, value, Outcome::Matched);
+                self.search(rest, ignore_case, depth + 1, tracer)
+            }
+            None => {
+                let m = self.matched();
+                let outcome = if m.is_some() {
+                    Outcome::Found
+                } else {
+                    Outcome::NoRoute
+                };
+                tracer.step(depth, child, value, outcome);
+                m
             }
-            return results;
         }
+    }
+}
 
-        if self.wild.is_some() && path.len() > 0 {
-            let enode = self.wild.as_ref().unwrap();
-            let node = enode.node.as_ref();
-            let handler = node.handler.as_ref()?;
+/// Outcome is the result of trying a child node while searching for a route.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// The segment was matched and the search continued below the child.
+    Matched,
+    /// The segment was not accepted by the child.
+    Rejected,
+    /// The path ended on the child, but no route was registered on it.
+    NoRoute,
+    /// The path ended on the child and the route registered on it was found.
+    Found,
+}
 
-            let mut m = Some(Match {
-                handler,
-                params: RequestData {
-                    params: Some(HashMap::new()),
-                },
-            });
-            m.as_mut()
-                .unwrap()
-                .params
-                .params
-                .as_mut()
-                .unwrap()
-                .insert(enode.id.as_ref(), path.to_owned());
-            return m;
+/// Step is a child node tried while searching for a route, see `Router::explain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// The depth of the child within the tree, its children being one level deeper.
+    pub depth: usize,
+    pub kind: NodeKind,
+    /// The segment the child was registered with, eg. `:id(\d+)`.
+    pub segment: String,
+    /// The part of the path tried against the child.
+    pub value: String,
+    pub outcome: Outcome,
+}
+
+// Tracer records the children tried while searching for a route; the unit tracer records nothing
+// so that routing itself pays no cost for it.
+trait Tracer {
+    fn step(&mut self, depth: usize, child: (NodeKind, &str), value: &str, outcome: Outcome);
+}
+
+impl Tracer for () {
+    fn step(&mut self, _: usize, _: (NodeKind, &str), _: &str, _: Outcome) {}
+}
+
+impl Tracer for Vec<Step> {
+    fn step(&mut self, depth: usize, child: (NodeKind, &str), value: &str, outcome: Outcome) {
+        self.push(Step {
+            depth,
+            kind: child.0,
+            segment: child.1.to_owned(),
+            value: value.to_owned(),
+            outcome,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{future, Stream};
+    use hyper::StatusCode;
+    use std::str;
+
+    fn tagged(tag: &'static str) -> Box<Handler> {
+        Box::new(
+            move |_req: Request, _data: RequestData| -> Box<Future<Item = Response, Error = Error>> {
+                Box::new(future::ok(
+                    Response::new().with_status(StatusCode::Ok).with_body(tag),
+                ))
+            },
+        )
+    }
+
+    fn tree(routes: &[(&str, &'static str)]) -> Node {
+        let mut node = Node::new();
+        for &(path, tag) in routes {
+            let route = Route {
+                handler: Arc::new(tagged(tag)),
+                info: RouteInfo::new(Method::Get, path, None),
+            };
+            node.add(&path[1..], route, false).unwrap();
         }
-        None
+        node
+    }
+
+    // returns the tag of the matched handler along with the captured params, sorted by name.
+    fn lookup(node: &Node, path: &str) -> Option<(String, Vec<(String, String)>)> {
+        let m = node.find(&path[1..])?;
+        let req = Request::new(Method::Get, path.parse().unwrap());
+        let res = m.routes[0]
+            .handler
+            .handle(req, RequestData::default())
+            .wait()
+            .unwrap();
+        let body = res.body().concat2().wait().unwrap();
+        let mut params: Vec<(String, String)> = m.params
+            .params
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect();
+        params.sort();
+        Some((str::from_utf8(&body).unwrap().to_owned(), params))
+    }
+
+    fn tag(node: &Node, path: &str) -> Option<String> {
+        lookup(node, path).map(|(tag, _)| tag)
+    }
+
+    #[test]
+    fn static_falls_back_to_param() {
+        let node = tree(&[("/test/new/edit", "static"), ("/test/:id/view", "param")]);
+        assert_eq!(tag(&node, "/test/new/edit"), Some("static".to_owned()));
+        assert_eq!(tag(&node, "/test/new/view"), Some("param".to_owned()));
+        assert_eq!(tag(&node, "/test/13/view"), Some("param".to_owned()));
+        assert_eq!(tag(&node, "/test/13/edit"), None);
+        assert_eq!(
+            lookup(&node, "/test/new/view").unwrap().1,
+            vec![(":id".to_owned(), "new".to_owned())]
+        );
+    }
+
+    #[test]
+    fn static_falls_back_to_wild() {
+        let node = tree(&[("/test/new/edit", "static"), ("/test/*rest", "wild")]);
+        assert_eq!(tag(&node, "/test/new/edit"), Some("static".to_owned()));
+        assert_eq!(tag(&node, "/test/new/view"), Some("wild".to_owned()));
+        assert_eq!(tag(&node, "/test/new"), Some("wild".to_owned()));
+        assert_eq!(
+            lookup(&node, "/test/new/view").unwrap().1,
+            vec![("*rest".to_owned(), "new/view".to_owned())]
+        );
+    }
+
+    #[test]
+    fn param_falls_back_to_wild() {
+        let node = tree(&[("/test/:id/view", "param"), ("/test/*rest", "wild")]);
+        assert_eq!(tag(&node, "/test/13/view"), Some("param".to_owned()));
+        assert_eq!(tag(&node, "/test/13/edit"), Some("wild".to_owned()));
+        assert_eq!(tag(&node, "/test/13"), Some("wild".to_owned()));
+        assert_eq!(tag(&node, "/test/"), None);
+    }
+
+    #[test]
+    fn static_param_and_wild() {
+        let node = tree(&[
+            ("/test/new", "static"),
+            ("/test/new/edit", "static-edit"),
+            ("/test/:id", "param"),
+            ("/test/:id/view", "param-view"),
+            ("/test/*rest", "wild"),
+        ]);
+        assert_eq!(tag(&node, "/test/new"), Some("static".to_owned()));
+        assert_eq!(tag(&node, "/test/new/edit"), Some("static-edit".to_owned()));
+        assert_eq!(tag(&node, "/test/new/view"), Some("param-view".to_owned()));
+        assert_eq!(tag(&node, "/test/new/other"), Some("wild".to_owned()));
+        assert_eq!(tag(&node, "/test/13"), Some("param".to_owned()));
+        assert_eq!(tag(&node, "/test/13/view"), Some("param-view".to_owned()));
+        assert_eq!(tag(&node, "/test/13/edit"), Some("wild".to_owned()));
+    }
+
+    #[test]
+    fn static_without_handler_falls_back() {
+        // the static node exists only as a prefix of a deeper route and has no handler itself
+        let node = tree(&[("/test/new/edit", "static"), ("/test/:id", "param")]);
+        assert_eq!(tag(&node, "/test/new"), Some("param".to_owned()));
+    }
+
+    #[test]
+    fn backtracks_across_several_levels() {
+        let node = tree(&[
+            ("/a/b/c/d", "static"),
+            ("/a/:x/c/e", "param"),
+            ("/a/b/:y/f", "inner-param"),
+            ("/a/*rest", "wild"),
+        ]);
+        assert_eq!(tag(&node, "/a/b/c/d"), Some("static".to_owned()));
+        assert_eq!(tag(&node, "/a/b/c/f"), Some("inner-param".to_owned()));
+        assert_eq!(tag(&node, "/a/b/c/e"), Some("param".to_owned()));
+        assert_eq!(tag(&node, "/a/b/c/g"), Some("wild".to_owned()));
+        assert_eq!(
+            lookup(&node, "/a/b/c/e").unwrap().1,
+            vec![(":x".to_owned(), "b".to_owned())]
+        );
+    }
+
+    #[test]
+    fn params_are_not_leaked_from_failed_branches() {
+        let node = tree(&[("/a/:x/:y/z", "deep"), ("/a/:x/b", "shallow")]);
+        assert_eq!(
+            lookup(&node, "/a/1/b").unwrap(),
+            (
+                "shallow".to_owned(),
+                vec![(":x".to_owned(), "1".to_owned())]
+            )
+        );
+    }
+
+    #[test]
+    fn constrained_params() {
+        let node = tree(&[
+            ("/orders/:id(\\d+)", "id"),
+            ("/orders/:token([A-Z]{8})", "token"),
+            ("/orders/:id(\\d+)/items", "items"),
+        ]);
+        assert_eq!(
+            lookup(&node, "/orders/42").unwrap(),
+            ("id".to_owned(), vec![(":id".to_owned(), "42".to_owned())])
+        );
+        assert_eq!(
+            lookup(&node, "/orders/ABCDEFGH").unwrap(),
+            (
+                "token".to_owned(),
+                vec![(":token".to_owned(), "ABCDEFGH".to_owned())]
+            )
+        );
+        assert_eq!(tag(&node, "/orders/42/items"), Some("items".to_owned()));
+        assert_eq!(tag(&node, "/orders/ABCDEFG"), None);
+        assert_eq!(tag(&node, "/orders/42a"), None);
+        assert_eq!(tag(&node, "/orders/ABCDEFGH/items"), None);
+    }
+
+    #[test]
+    fn constrained_params_registration_order() {
+        let node = tree(&[
+            ("/p/:num([0-9]+)", "num"),
+            ("/p/:word([a-z0-9]+)", "word"),
+            ("/p/:any", "any"),
+        ]);
+        assert_eq!(tag(&node, "/p/123"), Some("num".to_owned()));
+        assert_eq!(tag(&node, "/p/abc123"), Some("word".to_owned()));
+        assert_eq!(tag(&node, "/p/ABC"), Some("any".to_owned()));
+    }
+
+    #[test]
+    fn constrained_param_falls_back_to_wild() {
+        let node = tree(&[("/p/:id(\\d+)", "id"), ("/p/*rest", "wild")]);
+        assert_eq!(tag(&node, "/p/1"), Some("id".to_owned()));
+        assert_eq!(tag(&node, "/p/a"), Some("wild".to_owned()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_same_constraint_differing_ids() {
+        tree(&[("/p/:id(\\d+)", "id"), ("/p/:num(\\d+)/more", "num")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_invalid_constraint() {
+        tree(&[("/p/:id(\\d+[)", "id")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_unterminated_constraint() {
+        tree(&[("/p/:id(\\d+", "id")]);
+    }
+
+    #[test]
+    fn typed_params() {
+        let node = tree(&[
+            ("/t/:id<u64>", "u64"),
+            ("/t/:id<i64>", "i64"),
+            ("/t/:id<uuid>", "uuid"),
+            ("/t/:flag<bool>", "bool"),
+            ("/t/:name<str>", "str"),
+        ]);
+        assert_eq!(tag(&node, "/t/18446744073709551615"), Some("u64".to_owned()));
+        assert_eq!(tag(&node, "/t/-5"), Some("i64".to_owned()));
+        assert_eq!(
+            tag(&node, "/t/67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            Some("uuid".to_owned())
+        );
+        assert_eq!(tag(&node, "/t/true"), Some("bool".to_owned()));
+        assert_eq!(tag(&node, "/t/18446744073709551616"), Some("str".to_owned()));
+        assert_eq!(
+            tag(&node, "/t/67e55044-10b1-426f-9247-bb680e5fe0cz"),
+            Some("str".to_owned())
+        );
+    }
+
+    #[test]
+    fn typed_param_mismatch_falls_through() {
+        let node = tree(&[("/t/:id<u64>/view", "u64")]);
+        assert_eq!(tag(&node, "/t/12/view"), Some("u64".to_owned()));
+        assert_eq!(tag(&node, "/t/-12/view"), None);
+        assert_eq!(tag(&node, "/t/abc/view"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_unknown_type() {
+        tree(&[("/p/:id<u32>", "id")]);
+    }
+
+    #[test]
+    fn intra_segment_params() {
+        let node = tree(&[
+            ("/files/:name.:ext", "file"),
+            ("/v:version/status", "version"),
+            ("/@:user", "user"),
+            ("/@:user/posts", "posts"),
+        ]);
+        assert_eq!(
+            lookup(&node, "/files/report.pdf").unwrap(),
+            (
+                "file".to_owned(),
+                vec![
+                    (":ext".to_owned(), "pdf".to_owned()),
+                    (":name".to_owned(), "report".to_owned()),
+                ]
+            )
+        );
+        assert_eq!(
+            lookup(&node, "/v2/status").unwrap(),
+            (
+                "version".to_owned(),
+                vec![(":version".to_owned(), "2".to_owned())]
+            )
+        );
+        assert_eq!(
+            lookup(&node, "/@joe/posts").unwrap(),
+            ("posts".to_owned(), vec![(":user".to_owned(), "joe".to_owned())])
+        );
+        assert_eq!(tag(&node, "/@joe"), Some("user".to_owned()));
+        assert_eq!(tag(&node, "/@"), None);
+        assert_eq!(tag(&node, "/files/report"), None);
+        assert_eq!(tag(&node, "/files/.pdf"), None);
+        assert_eq!(tag(&node, "/v/status"), None);
+    }
+
+    #[test]
+    fn intra_segment_params_are_greedy() {
+        let node = tree(&[("/files/:name.:ext", "file")]);
+        assert_eq!(
+            lookup(&node, "/files/archive.tar.gz").unwrap().1,
+            vec![
+                (":ext".to_owned(), "gz".to_owned()),
+                (":name".to_owned(), "archive.tar".to_owned()),
+            ]
+        );
+
+        // constraints make the param give up characters
+        let node = tree(&[("/files/:name.:ext([a-z]+\\.[a-z]+)", "file")]);
+        assert_eq!(
+            lookup(&node, "/files/archive.tar.gz").unwrap().1,
+            vec![
+                (":ext".to_owned(), "tar.gz".to_owned()),
+                (":name".to_owned(), "archive".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn intra_segment_priority() {
+        let node = tree(&[
+            ("/files/latest.zip", "static"),
+            ("/files/:name.zip", "pattern"),
+            ("/files/:name", "param"),
+        ]);
+        assert_eq!(tag(&node, "/files/latest.zip"), Some("static".to_owned()));
+        assert_eq!(tag(&node, "/files/old.zip"), Some("pattern".to_owned()));
+        assert_eq!(tag(&node, "/files/old.tar"), Some("param".to_owned()));
+    }
+
+    #[test]
+    fn intra_segment_typed_params() {
+        let node = tree(&[("/v:major<u64>.:minor<u64>", "version"), ("/:page", "page")]);
+        assert_eq!(tag(&node, "/v1.2"), Some("version".to_owned()));
+        assert_eq!(tag(&node, "/v1.x"), Some("page".to_owned()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_adjacent_params() {
+        tree(&[("/files/:name:ext", "file")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_differing_pattern_ids() {
+        tree(&[("/files/:name.:ext", "file"), ("/files/:base.:suffix/x", "other")]);
+    }
+
+    #[test]
+    fn non_terminal_wild() {
+        let node = tree(&[
+            ("/repos/*path/blob", "blob"),
+            ("/repos/*path/tree/main", "tree"),
+            ("/repos/*path", "repo"),
+        ]);
+        assert_eq!(
+            lookup(&node, "/repos/rust-playground/lars/blob").unwrap(),
+            (
+                "blob".to_owned(),
+                vec![("*path".to_owned(), "rust-playground/lars".to_owned())]
+            )
+        );
+        assert_eq!(
+            lookup(&node, "/repos/a/blob/b/blob").unwrap().1,
+            vec![("*path".to_owned(), "a/blob/b".to_owned())]
+        );
+        assert_eq!(
+            lookup(&node, "/repos/a/b/tree/main").unwrap(),
+            (
+                "tree".to_owned(),
+                vec![("*path".to_owned(), "a/b".to_owned())]
+            )
+        );
+        assert_eq!(tag(&node, "/repos/a/b/tree"), Some("repo".to_owned()));
+        assert_eq!(tag(&node, "/repos/blob"), Some("repo".to_owned()));
+    }
+
+    #[test]
+    fn non_terminal_wild_without_fallback() {
+        let node = tree(&[("/repos/*path/blob", "blob")]);
+        assert_eq!(tag(&node, "/repos/a/b/blob"), Some("blob".to_owned()));
+        assert_eq!(tag(&node, "/repos/a/b"), None);
+        assert_eq!(tag(&node, "/repos/blob"), None);
+        assert_eq!(tag(&node, "/repos/a/blob/more"), None);
+    }
+
+    #[test]
+    fn wild_with_suffix() {
+        let node = tree(&[
+            ("/assets/*file.map", "map"),
+            ("/assets/*file.js", "js"),
+            ("/assets/*file", "file"),
+        ]);
+        assert_eq!(
+            lookup(&node, "/assets/js/app.js.map").unwrap(),
+            (
+                "map".to_owned(),
+                vec![("*file".to_owned(), "js/app.js".to_owned())]
+            )
+        );
+        assert_eq!(
+            lookup(&node, "/assets/js/app.js").unwrap(),
+            (
+                "js".to_owned(),
+                vec![("*file".to_owned(), "js/app".to_owned())]
+            )
+        );
+        assert_eq!(tag(&node, "/assets/app.css"), Some("file".to_owned()));
+        assert_eq!(tag(&node, "/assets/.map"), Some("file".to_owned()));
+
+        // only a `.` starts a suffix, other characters are part of the wildcard's name
+        let node = tree(&[("/static/*file-name", "file")]);
+        assert_eq!(
+            lookup(&node, "/static/css/app-1.css").unwrap().1,
+            vec![("*file-name".to_owned(), "css/app-1.css".to_owned())]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_differing_wild_ids_same_suffix() {
+        tree(&[("/assets/*file.map", "map"), ("/assets/*path.map/x", "other")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_param_in_wild_suffix() {
+        tree(&[("/assets/*file.:ext", "map")]);
+    }
+
+    #[test]
+    fn ignore_case() {
+        let node = tree(&[
+            ("/about", "about"),
+            ("/About/team", "team"),
+            ("/files/*path/Raw", "raw"),
+            ("/users/:name", "user"),
+        ]);
+        assert_eq!(tag(&node, "/ABOUT"), None);
+        assert_eq!(node.find_ignore_case("ABOUT").map(|_| ()), Some(()));
+
+        // the exact case is preferred, backtracking to any other
+        let m = node.find_ignore_case("about/Team").unwrap();
+        assert_eq!(m.canonical_path("about/Team"), Some("About/team".to_owned()));
+        let m = node.find_ignore_case("about").unwrap();
+        assert_eq!(m.canonical_path("about"), None);
+
+        let m = node.find_ignore_case("FILES/A/b/raw").unwrap();
+        assert_eq!(m.canonical_path("FILES/A/b/raw"), Some("files/A/b/Raw".to_owned()));
+        assert_eq!(m.params.raw_param("path"), Some("A/b"));
+    }
+
+    #[test]
+    fn decoded_params() {
+        let node = tree(&[
+            ("/users/:name", "user"),
+            ("/files/*path", "file"),
+            ("/download/:name.:ext", "download"),
+        ]);
+        assert_eq!(
+            lookup(&node, "/users/John%20Doe").unwrap().1,
+            vec![(":name".to_owned(), "John Doe".to_owned())]
+        );
+        assert_eq!(
+            lookup(&node, "/users/a%2Fb").unwrap().1,
+            vec![(":name".to_owned(), "a/b".to_owned())]
+        );
+        assert_eq!(
+            lookup(&node, "/files/my%20docs/a%2Fb%2f%41.txt").unwrap().1,
+            vec![("*path".to_owned(), "my docs/a%2Fb%2fA.txt".to_owned())]
+        );
+        // an encoded percent sign is kept too, so an encoded slash is never ambiguous
+        assert_eq!(
+            lookup(&node, "/files/a%2Fb").unwrap().1,
+            vec![("*path".to_owned(), "a%2Fb".to_owned())]
+        );
+        assert_eq!(
+            lookup(&node, "/files/a%252Fb").unwrap().1,
+            vec![("*path".to_owned(), "a%252Fb".to_owned())]
+        );
+        assert_eq!(
+            lookup(&node, "/files/100%25%20off").unwrap().1,
+            vec![("*path".to_owned(), "100%25 off".to_owned())]
+        );
+        assert_eq!(
+            lookup(&node, "/users/100%25").unwrap().1,
+            vec![(":name".to_owned(), "100%".to_owned())]
+        );
+        assert_eq!(
+            lookup(&node, "/download/r%C3%A9sum%C3%A9.pdf").unwrap().1,
+            vec![
+                (":ext".to_owned(), "pdf".to_owned()),
+                (":name".to_owned(), "résumé".to_owned()),
+            ]
+        );
+        // invalid UTF-8 is kept raw
+        assert_eq!(
+            lookup(&node, "/users/%FF").unwrap().1,
+            vec![(":name".to_owned(), "%FF".to_owned())]
+        );
+    }
+
+    #[test]
+    fn raw_params() {
+        let node = tree(&[("/users/:name/*rest", "user")]);
+        let m = node.find("users/John%20Doe/a%2Fb").unwrap();
+        assert_eq!(m.params.param::<String>("name"), Ok("John Doe".to_owned()));
+        assert_eq!(m.params.raw_param("name"), Some("John%20Doe"));
+        assert_eq!(m.params.raw_param("rest"), Some("a%2Fb"));
+        assert_eq!(m.params.raw_param("missing"), None);
+        assert_eq!(m.params.raw_params().map(|p| p.len()), Some(2));
+    }
+
+    #[test]
+    fn request_data_param() {
+        let mut params = HashMap::new();
+        params.insert(":id", "42".to_owned());
+        params.insert("*rest", "a/b".to_owned());
+        let data = RequestData {
+            params: Some(params),
+            raw_params: None,
+            query: None,
+            base: String::new(),
+        };
+        assert_eq!(data.param::<u64>("id"), Ok(42));
+        assert_eq!(data.param::<String>("rest"), Ok("a/b".to_owned()));
+        assert_eq!(
+            data.param::<u64>("missing"),
+            Err(ParamError::Missing("missing".to_owned()))
+        );
+        match data.param::<bool>("id") {
+            Err(ParamError::Invalid { name, value, .. }) => {
+                assert_eq!(name, "id");
+                assert_eq!(value, "42");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let data = RequestData::default();
+        assert_eq!(
+            data.param::<u64>("id"),
+            Err(ParamError::Missing("id".to_owned()))
+        );
+    }
+
+    #[test]
+    fn no_match() {
+        let node = tree(&[("/test/new", "static"), ("/test/:id/view", "param")]);
+        assert_eq!(tag(&node, "/test/13"), None);
+        assert_eq!(tag(&node, "/other"), None);
+        assert_eq!(tag(&node, "/test/new/view/more"), None);
+    }
+
+    #[test]
+    fn trace_backtracks() {
+        let node = tree(&[("/test/new/edit", "static"), ("/test/:id/view", "param")]);
+        let mut steps = Vec::new();
+        assert!(node.trace("test/new/view", &mut steps).is_some());
+        let steps: Vec<(usize, NodeKind, &str, &str, Outcome)> = steps
+            .iter()
+            .map(|s| (s.depth, s.kind, s.segment.as_str(), s.value.as_str(), s.outcome))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (0, NodeKind::Static, "test", "test", Outcome::Matched),
+                (1, NodeKind::Static, "new", "new", Outcome::Matched),
+                (1, NodeKind::Param, ":id", "new", Outcome::Matched),
+                (2, NodeKind::Static, "view", "view", Outcome::Found),
+            ]
+        );
+
+        let mut steps = Vec::new();
+        assert!(node.trace("test/13", &mut steps).is_none());
+        assert_eq!(steps.last().map(|s| s.outcome), Some(Outcome::NoRoute));
     }
 }
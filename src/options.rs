@@ -0,0 +1,170 @@
+use hyper::StatusCode;
+
+/// TrailingSlash determines how a request differing from a route only by a trailing slash is
+/// handled, eg. `/users/` when only `/users` is registered or the reverse.
+///
+/// The alternate path is only looked up when the request path itself does not match any route,
+/// so there is no cost for requests which do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrailingSlash {
+    /// The paths are distinct and the request is not found, this is the default.
+    Strict,
+    /// The request is redirected to the registered path using the given status, which should be
+    /// either `MovedPermanently` (301) or `PermanentRedirect` (308); the latter preserves the
+    /// method.
+    Redirect(StatusCode),
+    /// The request is handled by the registered route as if it had been requested.
+    Match,
+}
+
+// returns the path, without its leading slash, with its trailing slash added or removed; the root
+// path has no alternative.
+pub fn toggle_trailing_slash(path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+    match path.strip_suffix('/') {
+        Some(p) => Some(p.to_owned()),
+        None => Some(format!("{}/", path)),
+    }
+}
+
+/// CaseSensitivity determines whether the static segments of a request path must match the case
+/// of the route, eg. `/About` when only `/about` is registered. Parameters and wildcards always
+/// capture the path as sent.
+///
+/// Only ASCII letters are compared regardless of case, and only when the request path itself does
+/// not match any route, so there is no cost for requests which do. Static text sharing a segment
+/// with params, eg. `.json` in `:name.json`, and the suffix of a wildcard, eg. `.map` in
+/// `*file.map`, are always matched in their registered case.
+///
+/// Along with a `TrailingSlash` other than `Strict`, a request differing from a route by both its
+/// case and a trailing slash is handled too, eg. `/About/` for `/about`; its path is corrected by
+/// whichever of the two options redirects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseSensitivity {
+    /// Static segments must match exactly, this is the default.
+    Sensitive,
+    /// The request is redirected to the path in the case the route was registered, using the
+    /// given status.
+    Redirect(StatusCode),
+    /// The request is handled by the route as if it had been requested in the registered case.
+    Insensitive,
+}
+
+/// CleanPath determines whether request paths are cleaned before routing, collapsing duplicate
+/// slashes and resolving dot segments, eg. `//users/./x/../5` becomes `/users/5`.
+///
+/// Dot segments are also recognised when percent-encoded, eg. `%2e%2e`, so a wildcard can never
+/// capture a path climbing above the route it matched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CleanPath {
+    /// Paths are routed as requested, this is the default.
+    Off,
+    /// Requests for unclean paths are redirected to the clean path using the given status.
+    Redirect(StatusCode),
+    /// Requests are routed using the clean path, the request itself is left untouched.
+    Route,
+}
+
+// returns the cleaned path, or `None` if it's already clean. A trailing slash is preserved, and
+// added when the path ends with a dot segment as it refers to a directory.
+pub fn clean_path(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing = false;
+
+    for segment in path.split('/').skip(1) {
+        trailing = true;
+        match dot_segment(segment) {
+            Some(2) => {
+                segments.pop();
+            }
+            Some(_) => {}
+            None if segment.is_empty() => {}
+            None => {
+                trailing = false;
+                segments.push(segment);
+            }
+        }
+    }
+
+    let mut clean = String::with_capacity(path.len());
+    for segment in &segments {
+        clean.push('/');
+        clean.push_str(segment);
+    }
+    if trailing || clean.is_empty() {
+        clean.push('/');
+    }
+
+    if clean == path {
+        return None;
+    }
+    Some(clean)
+}
+
+// returns the number of dots if the segment is `.` or `..`, including percent-encoded forms.
+fn dot_segment(segment: &str) -> Option<usize> {
+    let mut dots = 0;
+    let mut rest = segment;
+    while !rest.is_empty() {
+        if rest.starts_with('.') {
+            rest = &rest[1..];
+        } else if rest.len() >= 3 && rest.as_bytes()[..3].eq_ignore_ascii_case(b"%2e") {
+            rest = &rest[3..];
+        } else {
+            return None;
+        }
+        dots += 1;
+    }
+    match dots {
+        1 | 2 => Some(dots),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean() {
+        let cases = [
+            ("/", None),
+            ("/users", None),
+            ("/users/", None),
+            ("//users///5", Some("/users/5")),
+            ("/users/./5", Some("/users/5")),
+            ("/users/x/../5", Some("/users/5")),
+            ("/users/5/.", Some("/users/5/")),
+            ("/users/5/..", Some("/users/")),
+            ("/users//", Some("/users/")),
+            ("/../../etc/passwd", Some("/etc/passwd")),
+            ("/static/%2e%2E/secret", Some("/secret")),
+            ("/static/.%2e/secret", Some("/secret")),
+            ("/static/...", None),
+            ("/static/%2e%2e%2e", None),
+            ("/.", Some("/")),
+            ("*", None),
+        ];
+        for &(path, expected) in cases.iter() {
+            assert_eq!(
+                clean_path(path),
+                expected.map(|e| e.to_owned()),
+                "cleaning {}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn toggle() {
+        assert_eq!(toggle_trailing_slash(""), None);
+        assert_eq!(toggle_trailing_slash("users"), Some("users/".to_owned()));
+        assert_eq!(toggle_trailing_slash("users/"), Some("users".to_owned()));
+    }
+}
@@ -0,0 +1,469 @@
+use percent_encoding::{
+    percent_decode, utf8_percent_encode, DEFAULT_ENCODE_SET, PATH_SEGMENT_ENCODE_SET,
+};
+use regex::Regex;
+
+use error::{RouteError, UrlError};
+
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+
+/// ParamType is the set of built-in converters that may be declared on a route parameter
+/// using the `:name<type>` syntax, eg. `/users/:id<u64>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamType {
+    U64,
+    I64,
+    Uuid,
+    Bool,
+    Str,
+}
+
+impl ParamType {
+    fn from_name(name: &str) -> Option<ParamType> {
+        match name {
+            "u64" => Some(ParamType::U64),
+            "i64" => Some(ParamType::I64),
+            "uuid" => Some(ParamType::Uuid),
+            "bool" => Some(ParamType::Bool),
+            "str" => Some(ParamType::Str),
+            _ => None,
+        }
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        match *self {
+            ParamType::U64 => value.parse::<u64>().is_ok(),
+            ParamType::I64 => value.parse::<i64>().is_ok(),
+            ParamType::Uuid => is_uuid(value),
+            ParamType::Bool => value.parse::<bool>().is_ok(),
+            ParamType::Str => true,
+        }
+    }
+}
+
+// checks for the hyphenated form eg. `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36 && value.char_indices().all(|(i, c)| match i {
+        8 | 13 | 18 | 23 => c == '-',
+        _ => c.is_ascii_hexdigit(),
+    })
+}
+
+/// Constraint restricts which segments a route parameter will accept.
+#[derive(Debug, Clone)]
+pub enum Constraint {
+    Regex(Regex),
+    Type(ParamType),
+}
+
+impl Constraint {
+    /// Returns whether the constraint accepts the value, which must already be percent-decoded.
+    pub fn accepts(&self, value: &str) -> bool {
+        match *self {
+            Constraint::Regex(ref re) => re.is_match(value),
+            Constraint::Type(ref t) => t.accepts(value),
+        }
+    }
+
+    /// Returns whether the constraint accepts the segment captured from a request path once it
+    /// is percent-decoded, as the handler reads it.
+    pub fn accepts_segment(&self, segment: &str) -> bool {
+        self.accepts(&decode_segment(segment))
+    }
+
+    /// Returns whether both constraints accept exactly the same segments.
+    pub fn same_as(&self, other: &Constraint) -> bool {
+        match (self, other) {
+            (Constraint::Regex(a), Constraint::Regex(b)) => a.as_str() == b.as_str(),
+            (Constraint::Type(a), Constraint::Type(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Piece is part of a route segment, either static text or a parameter with an optional constraint.
+#[derive(Debug, Clone)]
+pub enum Piece {
+    Static(String),
+    Param(String, Option<Constraint>),
+}
+
+/// Splits a route segment into its static and parameter pieces, eg. `:name.:ext` becomes
+/// the param `:name`, the static `.` and the param `:ext`.
+///
+/// A parameter name consists of alphanumeric characters and underscores and may be followed by a
+/// constraint, being either a regular expression eg. `:id(\d+)` or a built-in type eg. `:id<u64>`.
+///
+/// An error describing the problem is returned if a parameter has no name eg. `/:(\d+)`, if the
+/// constraint is not closed eg. `:id(\d+`, is not a valid regular expression eg. `:id(\d+[)` or
+/// is not one of the built-in types eg. `:id<u32>`.
+pub fn parse_segment(segment: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = segment;
+
+    while !rest.is_empty() {
+        if !rest.starts_with(':') {
+            let end = rest.find(':').unwrap_or(rest.len());
+            pieces.push(Piece::Static(rest[..end].to_owned()));
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest[1..]
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(rest.len(), |i| i + 1);
+        if end == 1 {
+            return Err("parameter name missing".to_owned());
+        }
+        let id = &rest[..end];
+        rest = &rest[end..];
+
+        let constraint = match rest.chars().next() {
+            Some('(') | Some('<') => {
+                let close = constraint_end(rest)
+                    .ok_or_else(|| "unterminated parameter constraint".to_owned())?;
+                let c = parse_constraint(&rest[..close + 1])?;
+                rest = &rest[close + 1..];
+                Some(c)
+            }
+            _ => None,
+        };
+        pieces.push(Piece::Param(id.to_owned(), constraint));
+    }
+    Ok(pieces)
+}
+
+// returns the index of the character closing the constraint at the start of `s`, accounting for
+// nested and escaped parentheses within regular expressions.
+fn constraint_end(s: &str) -> Option<usize> {
+    if s.starts_with('<') {
+        return s.find('>');
+    }
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_constraint(c: &str) -> Result<Constraint, String> {
+    let inner = &c[1..c.len() - 1];
+    if c.starts_with('(') {
+        Regex::new(&format!("^(?:{})$", inner))
+            .map(Constraint::Regex)
+            .map_err(|e| format!("invalid parameter constraint: {}", e))
+    } else {
+        ParamType::from_name(inner)
+            .map(Constraint::Type)
+            .ok_or_else(|| format!("unknown parameter type '{}'", inner))
+    }
+}
+
+/// SegmentPattern matches a single segment mixing static text and parameters, eg. `:name.:ext`,
+/// `v:version` or `@:user`.
+#[derive(Debug, Clone)]
+pub struct SegmentPattern {
+    pieces: Vec<Piece>,
+}
+
+impl SegmentPattern {
+    /// Creates a new `SegmentPattern` from the pieces of a segment, returning an error if two
+    /// parameters are adjacent eg. `:a:b`, as there would be no way to tell them apart.
+    pub fn new(pieces: Vec<Piece>) -> Result<SegmentPattern, String> {
+        for w in pieces.windows(2) {
+            if let (Piece::Param(..), Piece::Param(..)) = (&w[0], &w[1]) {
+                return Err("adjacent parameters must be separated by static text".to_owned());
+            }
+        }
+        Ok(SegmentPattern { pieces })
+    }
+
+    /// Returns whether both patterns would match exactly the same segments, and if so whether
+    /// their parameters are also named the same.
+    pub fn compare(&self, other: &SegmentPattern) -> Option<bool> {
+        if self.pieces.len() != other.pieces.len() {
+            return None;
+        }
+        let mut same_ids = true;
+        for (a, b) in self.pieces.iter().zip(other.pieces.iter()) {
+            match (a, b) {
+                (Piece::Static(a), Piece::Static(b)) if a == b => {}
+                (Piece::Param(a_id, a), Piece::Param(b_id, b)) => {
+                    let same = match (a.as_ref(), b.as_ref()) {
+                        (Some(a), Some(b)) => a.same_as(b),
+                        (None, None) => true,
+                        _ => false,
+                    };
+                    if !same {
+                        return None;
+                    }
+                    same_ids = same_ids && a_id == b_id;
+                }
+                _ => return None,
+            }
+        }
+        Some(same_ids)
+    }
+
+    /// Matches the segment, pushing each captured parameter onto `params`.
+    ///
+    /// When a parameter is followed by static text it captures as much of the segment as it can,
+    /// eg. `:name.:ext` captures `archive.tar` and `gz` from `archive.tar.gz`, giving up characters
+    /// only if the remainder of the pattern or a constraint would otherwise fail.
+    pub fn matches<'a, 'b>(&'a self, segment: &'b str, params: &mut Vec<(&'a str, &'b str)>) -> bool {
+        let start = params.len();
+        if match_pieces(&self.pieces, segment, params) {
+            return true;
+        }
+        params.truncate(start);
+        false
+    }
+}
+
+fn match_pieces<'a, 'b>(
+    pieces: &'a [Piece],
+    segment: &'b str,
+    params: &mut Vec<(&'a str, &'b str)>,
+) -> bool {
+    let (piece, rest) = match pieces.split_first() {
+        Some(p) => p,
+        None => return segment.is_empty(),
+    };
+
+    match *piece {
+        Piece::Static(ref s) => {
+            segment.starts_with(s.as_str()) && match_pieces(rest, &segment[s.len()..], params)
+        }
+        Piece::Param(ref id, ref constraint) => {
+            let accepts = |value: &str| {
+                !value.is_empty() && match *constraint {
+                    Some(ref c) => c.accepts_segment(value),
+                    None => true,
+                }
+            };
+
+            let next = match rest.first() {
+                Some(Piece::Static(s)) => s,
+                _ => {
+                    if !accepts(segment) {
+                        return false;
+                    }
+                    params.push((id, segment));
+                    return true;
+                }
+            };
+
+            for (i, _) in segment.rmatch_indices(next.as_str()) {
+                let value = &segment[..i];
+                if !accepts(value) {
+                    continue;
+                }
+                params.push((id, value));
+                if match_pieces(rest, &segment[i..], params) {
+                    return true;
+                }
+                params.pop();
+            }
+            false
+        }
+    }
+}
+
+/// Splits a wildcard segment into its id, including the leading `*`, and the static suffix
+/// following it, which starts at the first `.` eg. `*file` and `.map` for `*file.map`. Without a
+/// `.` the whole segment is the id, eg. `*file-name`.
+pub fn split_wildcard(segment: &str) -> (&str, &str) {
+    segment.split_at(segment.find('.').unwrap_or(segment.len()))
+}
+
+// returns the segment percent-decoded, or as is if it does not decode to valid UTF-8.
+fn decode_segment(segment: &str) -> Cow<'_, str> {
+    percent_decode(segment.as_bytes())
+        .decode_utf8()
+        .unwrap_or(Cow::Borrowed(segment))
+}
+
+/// Expands a path containing optional trailing parameters, eg. `/archive/:year?/:month?`, into
+/// every path it may match, from the shortest `/archive` to the longest `/archive/:year/:month`.
+///
+/// An error is returned if a required segment follows an optional parameter eg.
+/// `/archive/:year?/posts`.
+pub fn expand_optional(path: &str) -> Result<Vec<String>, RouteError> {
+    let mut paths = Vec::new();
+    let mut current = String::new();
+    let mut optional = false;
+
+    for (i, segment) in path.split('/').enumerate() {
+        if segment.starts_with(':') && segment.ends_with('?') {
+            if !optional {
+                paths.push(if current.is_empty() {
+                    "/".to_owned()
+                } else {
+                    current.clone()
+                });
+            }
+            optional = true;
+            current.push('/');
+            current.push_str(&segment[..segment.len() - 1]);
+            paths.push(current.clone());
+            continue;
+        }
+        if optional {
+            return Err(RouteError::InvalidSegment {
+                pattern: path.to_owned(),
+                segment: segment.to_owned(),
+                reason: "required segment after an optional parameter".to_owned(),
+            });
+        }
+        if i > 0 {
+            current.push('/');
+        }
+        current.push_str(segment);
+    }
+
+    if !optional {
+        paths.push(current);
+    }
+    Ok(paths)
+}
+
+define_encode_set! {
+    // the characters encoded in a wildcard value, which unlike a param may contain slashes.
+    pub WILDCARD_ENCODE_SET = [DEFAULT_ENCODE_SET] | {'%'}
+}
+
+/// Builds the path of the route named `name` with the given pattern, substituting each parameter
+/// and wildcard by its percent-encoded value, given by name without its leading `:` or `*`.
+///
+/// Optional parameters may be left out, in which case so must every following one. An error is
+/// returned if a required value is missing, a value is not accepted by its parameter or a value
+/// is given for a parameter the route does not have.
+pub fn format_path(name: &str, pattern: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+    let mut used = vec![false; params.len()];
+    let mut value = |id: &str| {
+        params.iter().position(|p| p.0 == &id[1..]).map(|i| {
+            used[i] = true;
+            params[i].1
+        })
+    };
+    let missing = |id: &str| UrlError::MissingParam {
+        route: name.to_owned(),
+        param: id[1..].to_owned(),
+    };
+    let invalid = |id: &str, value: &str| UrlError::InvalidParam {
+        route: name.to_owned(),
+        param: id[1..].to_owned(),
+        value: value.to_owned(),
+    };
+
+    let mut path = String::new();
+    let mut omitted: Option<&str> = None;
+    for segment in pattern.split('/').skip(1) {
+        if segment.starts_with('*') {
+            let (id, suffix) = split_wildcard(segment);
+            let v = value(id).ok_or_else(|| missing(id))?;
+            if v.is_empty() {
+                return Err(invalid(id, v));
+            }
+            path.push('/');
+            path.extend(utf8_percent_encode(v, WILDCARD_ENCODE_SET));
+            path.push_str(suffix);
+            continue;
+        }
+
+        let optional = segment.starts_with(':') && segment.ends_with('?');
+        let segment = if optional {
+            &segment[..segment.len() - 1]
+        } else {
+            segment
+        };
+        // the pattern was already parsed when the route was registered
+        let pieces = parse_segment(segment).unwrap_or_default();
+        if optional {
+            if let Some(Piece::Param(ref id, _)) = pieces.first() {
+                if value(id).is_none() {
+                    omitted = omitted.or(Some(&segment[..id.len()]));
+                    continue;
+                }
+            }
+        }
+        if let Some(id) = omitted {
+            return Err(missing(id));
+        }
+
+        path.push('/');
+        for piece in &pieces {
+            match *piece {
+                Piece::Static(ref s) => path.push_str(s),
+                Piece::Param(ref id, ref constraint) => {
+                    let v = value(id).ok_or_else(|| missing(id))?;
+                    let accepted = match *constraint {
+                        Some(ref c) => c.accepts(v),
+                        None => true,
+                    };
+                    if v.is_empty() || !accepted {
+                        return Err(invalid(id, v));
+                    }
+                    path.extend(utf8_percent_encode(v, PATH_SEGMENT_ENCODE_SET));
+                }
+            }
+        }
+    }
+
+    if let Some(i) = used.iter().position(|u| !u) {
+        return Err(UrlError::UnknownParam {
+            route: name.to_owned(),
+            param: params[i].0.to_owned(),
+        });
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+/// ParamError is returned when a typed parameter cannot be retrieved from the `RequestData`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamError {
+    /// No parameter with the given name was captured for this route.
+    Missing(String),
+    /// The captured value could not be converted into the requested type.
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParamError::Missing(ref name) => write!(f, "parameter '{}' not found", name),
+            ParamError::Invalid {
+                ref name,
+                ref value,
+                ref reason,
+            } => write!(
+                f,
+                "parameter '{}' with value '{}' is invalid: {}",
+                name, value, reason
+            ),
+        }
+    }
+}
+
+impl error::Error for ParamError {}
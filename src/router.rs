@@ -3,24 +3,207 @@ extern crate hyper;
 
 use std::collections::HashMap;
 use std::io;
-use std::sync::Arc;
+use std::slice;
+use std::sync::{Arc, RwLock};
 
 use futures::Future;
 use hyper::Method;
 use hyper::server::{NewService, Request, Response, Service};
 
-use node::{Handler, Node, RequestData};
+use error::UrlError;
+use explain::{self, Explanation};
+use export;
+use host::HostPattern;
+use node::{Handler, Node, RequestData, RouteInfo};
+use param;
 
 pub type Routes = HashMap<Method, Node>;
 
+/// HostRoutes are the routes registered for a host pattern, or without a host, along with the
+/// parameters captured from the host of a request.
+pub type HostRoutes<'a, 'h> = (Option<&'a str>, &'a Routes, Vec<(&'a str, &'h str)>);
+
+/// RouteTable holds the routes registered without a host, along with those of each host ordered
+/// from the most specific host to the least.
+pub struct RouteTable {
+    pub routes: Routes,
+    pub hosts: Vec<(HostPattern, Routes)>,
+}
+
+impl RouteTable {
+    /// Returns the routes for the given host, most specific first and ending with the routes
+    /// registered without a host, along with the host pattern and the parameters captured from it.
+    pub fn routes_for<'a, 'h>(&'a self, host: Option<&'h str>) -> Vec<HostRoutes<'a, 'h>> {
+        let mut routes = Vec::new();
+        if let Some(host) = host {
+            for (pattern, tree) in &self.hosts {
+                if let Some(params) = pattern.matches(host) {
+                    routes.push((Some(pattern.as_str()), tree, params));
+                }
+            }
+        }
+        routes.push((None, &self.routes, Vec::new()));
+        routes
+    }
+
+    /// Returns the information of every route, ordered by host, pattern, method and conditions. A
+    /// route with optional parameters is registered once for each of the paths it matches, but
+    /// listed once.
+    pub fn infos(&self) -> Vec<RouteInfo> {
+        let mut infos = Vec::new();
+        for node in self.routes.values().chain(self.hosts.iter().flat_map(|h| h.1.values())) {
+            node.routes(&mut infos);
+        }
+
+        // routes sharing a method and path are ordered by their conditions, rather than the order
+        // they are tried, so that those registered for several optional expansions are adjacent
+        let key = |r: &RouteInfo| {
+            let conditions = r.conditions.to_string();
+            (r.host.clone(), r.pattern.clone(), r.method.to_string(), conditions)
+        };
+        let mut routes: Vec<RouteInfo> = infos.into_iter().cloned().collect();
+        routes.sort_by_key(key);
+        routes.dedup_by(|a, b| key(a) == key(b));
+        routes
+    }
+}
+
 pub struct Router {
     pub handler: Arc<Box<Handler>>,
+    // the pattern of each named route, by name
+    pub(crate) names: Arc<HashMap<String, String>>,
+    // every registered route, ordered by host, pattern, method and conditions
+    pub(crate) routes: Arc<Vec<RouteInfo>>,
+    // the routing tree, exported by `to_dot` and `to_json` and traced by `explain`
+    pub(crate) table: Arc<RouteTable>,
 }
 
 impl Router {
     pub fn new(handler: Box<Handler>) -> Router {
         Router {
             handler: Arc::new(handler),
+            names: Arc::new(HashMap::new()),
+            routes: Arc::new(Vec::new()),
+            table: Arc::new(RouteTable {
+                routes: Routes::new(),
+                hosts: Vec::new(),
+            }),
+        }
+    }
+
+    /// Returns the information of every registered route, ordered by host, pattern, method
+    /// and conditions.
+    ///
+    /// A route with optional parameters is listed once. A mounted router is listed as the routes
+    /// handing requests over to it, for the prefix and the wildcard below it, rather than as the
+    /// routes of the mounted router.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder};
+    /// use hyper::{Error, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let router = RouteBuilder::new()
+    ///     .get("/", page)
+    ///     .post("/users", page)
+    ///     .finalize();
+    ///
+    /// for route in router.routes() {
+    ///     println!("{} {}", route.method, route.pattern);
+    /// }
+    ///
+    /// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("page"),
+    ///    ))
+    /// }
+    /// ```
+    pub fn routes(&self) -> slice::Iter<'_, RouteInfo> {
+        self.routes.iter()
+    }
+
+    /// Returns the routing tree as a Graphviz DOT digraph, eg. to render it using
+    /// `dot -Tsvg routes.dot`.
+    ///
+    /// Each node is a segment of the registered paths, labelled with the methods having a route
+    /// registered on it, and its children are listed in the order they are tried; params and
+    /// patterns are dashed, wildcards dotted. The routes of each host form a separate tree.
+    pub fn to_dot(&self) -> String {
+        export::to_dot(&self.table)
+    }
+
+    /// Returns the routing tree as pretty printed JSON, with the tree of the routes registered
+    /// without a host under `routes` and those of each host under `hosts`.
+    ///
+    /// Each node lists its `segment`, its `kind` being one of `root`, `static`, `pattern`, `param`
+    /// or `wildcard`, the `methods` having a route registered on it and its `children` in the
+    /// order they are tried.
+    pub fn to_json(&self) -> String {
+        export::to_json(&self.table)
+    }
+
+    /// Explains how a request for the method and uri would be routed, listing for the routes of
+    /// each host searched every child node tried in turn: the segment of the path it was tried
+    /// against and whether it matched, was rejected or ended the search. The uri is either a path,
+    /// or an absolute uri whose host selects the routes searched, eg. `http://api.example.com/x`.
+    ///
+    /// The explanation also lists the other methods having a route matching the path. The
+    /// fallbacks tried once no route matches, cleaning the path, ignoring its case or toggling
+    /// its trailing slash, are not explained; nor is the routing within a mounted router.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate futures;
+    /// extern crate hyper;
+    /// extern crate lars;
+    ///
+    /// use lars::{RequestData, RouteBuilder};
+    /// use hyper::{Error, Method, Request, Response, StatusCode};
+    /// use futures::{Future, future};
+    ///
+    /// let router = RouteBuilder::new()
+    ///     .get("/users/:id(\\d+)", page)
+    ///     .get("/users/me", page)
+    ///     .finalize();
+    ///
+    /// let explanation = router.explain(Method::Get, "/users/5");
+    /// assert_eq!(explanation.route.unwrap().pattern, "/users/:id(\\d+)");
+    /// println!("{}", router.explain(Method::Get, "/users/bob"));
+    ///
+    /// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+    ///    Box::new(future::ok(
+    ///        Response::new()
+    ///            .with_status(StatusCode::Ok)
+    ///            .with_body("page"),
+    ///    ))
+    /// }
+    /// ```
+    pub fn explain(&self, method: Method, uri: &str) -> Explanation {
+        explain::explain(&self.table, method, uri)
+    }
+
+    /// Builds the path of the named route, substituting each parameter by the percent-encoded
+    /// value given for it by name, without its leading `:` or `*`.
+    ///
+    /// Optional parameters may be left out, provided every following one is too. An error is
+    /// returned if the route does not exist, a required value is missing, a value would not be
+    /// matched by its parameter, or a value is given for a parameter the route does not have.
+    ///
+    /// The path is that of the route as registered, including the prefix of its groups, without
+    /// the host it is restricted to if any.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        match self.names.get(name) {
+            Some(pattern) => param::format_path(name, pattern, params),
+            None => Err(UrlError::UnknownRoute(name.to_owned())),
         }
     }
 }
@@ -33,13 +216,99 @@ impl NewService for Router {
 
     fn new_service(&self) -> io::Result<Self::Instance> {
         Ok(RouterService {
-            handler: self.handler.clone(),
+            source: Source::Fixed(self.handler.clone()),
+        })
+    }
+}
+
+/// SwappableRouter serves the routes of a `Router` which may be replaced at runtime, eg. to roll
+/// out a feature or load a plugin without restarting the server.
+///
+/// Routes are added or removed by building a new `Router` with a `RouteBuilder`, so that they are
+/// validated as a whole, and swapping it in. Requests already being handled finish on the router
+/// they started on, while every `RouterService` handed out picks up the new router on its next
+/// request.
+///
+/// # Examples
+///
+/// ```
+/// extern crate futures;
+/// extern crate hyper;
+/// extern crate lars;
+///
+/// use lars::{RequestData, RouteBuilder, SwappableRouter};
+/// use hyper::{Error, Request, Response, StatusCode};
+/// use futures::{Future, future};
+///
+/// let routes = SwappableRouter::new(RouteBuilder::new().get("/", page).finalize());
+///
+/// // later on, eg. once a feature is enabled
+/// routes.swap(
+///     RouteBuilder::new()
+///         .get("/", page)
+///         .get("/beta", page)
+///         .finalize(),
+/// );
+/// assert_eq!(routes.current().routes().count(), 2);
+///
+/// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+///    Box::new(future::ok(
+///        Response::new()
+///            .with_status(StatusCode::Ok)
+///            .with_body("page"),
+///    ))
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SwappableRouter {
+    router: Arc<RwLock<Arc<Router>>>,
+}
+
+impl SwappableRouter {
+    pub fn new(router: Router) -> SwappableRouter {
+        SwappableRouter {
+            router: Arc::new(RwLock::new(Arc::new(router))),
+        }
+    }
+
+    /// Returns the router currently serving requests, eg. to build urls or list its routes.
+    pub fn current(&self) -> Arc<Router> {
+        current(&self.router)
+    }
+
+    /// Replaces the router serving requests, returning the one it replaced.
+    pub fn swap(&self, router: Router) -> Arc<Router> {
+        // the lock only guards the replacement of an `Arc`, which cannot be left half done
+        let mut current = self.router.write().unwrap_or_else(|e| e.into_inner());
+        ::std::mem::replace(&mut *current, Arc::new(router))
+    }
+}
+
+fn current(router: &RwLock<Arc<Router>>) -> Arc<Router> {
+    router.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+impl NewService for SwappableRouter {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Instance = RouterService;
+
+    fn new_service(&self) -> io::Result<Self::Instance> {
+        Ok(RouterService {
+            source: Source::Swappable(self.router.clone()),
         })
     }
 }
 
 pub struct RouterService {
-    handler: Arc<Box<Handler>>,
+    source: Source,
+}
+
+// Source is where a `RouterService` takes the handler of each request from.
+enum Source {
+    Fixed(Arc<Box<Handler>>),
+    Swappable(Arc<RwLock<Arc<Router>>>),
 }
 
 impl Service for RouterService {
@@ -49,6 +318,12 @@ impl Service for RouterService {
     type Future = Box<Future<Item = Response, Error = hyper::Error>>;
 
     fn call(&self, req: Request) -> Self::Future {
-        self.handler.handle(req, RequestData { params: None })
+        match self.source {
+            Source::Fixed(ref handler) => handler.handle(req, RequestData::default()),
+            // the router is held for this call only, the future it returns owns what it needs
+            Source::Swappable(ref router) => {
+                current(router).handler.handle(req, RequestData::default())
+            }
+        }
     }
 }
@@ -0,0 +1,293 @@
+use config::{self, HandlerRegistry};
+use error::ConfigError;
+use router::SwappableRouter;
+use RouteBuilder;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// ConfigWatcher reloads the routes of a config file into a `SwappableRouter` whenever the file
+/// changes, see `RouteBuilder::load_config` for its format.
+///
+/// The file is polled at a regular interval, one second by default, and reloaded when its
+/// contents differ from those last seen, once its size and modification time have stayed the same
+/// across two polls so that a file still being written is not loaded. The new routes are fully
+/// validated and finalized before being swapped in; if they are invalid, including when the file
+/// lists no routes at all, the previous routes keep being served until the file changes again.
+/// The outcome of each reload is passed to the `on_reload` callback, if any.
+///
+/// # Examples
+///
+/// ```no_run
+/// extern crate futures;
+/// extern crate hyper;
+/// extern crate lars;
+///
+/// use lars::{ConfigWatcher, HandlerRegistry, RequestData, RouteBuilder, SwappableRouter};
+/// use hyper::{Error, Request, Response, StatusCode};
+/// use futures::{Future, future};
+///
+/// let router = SwappableRouter::new(RouteBuilder::new().finalize());
+/// let watcher = ConfigWatcher::new("routes.toml", HandlerRegistry::new().handler("page", page))
+///     .on_reload(|result| match result {
+///         Ok(()) => println!("routes reloaded"),
+///         Err(e) => eprintln!("routes.toml: {}", e),
+///     });
+/// watcher.reload(&router).unwrap();
+///
+/// // the routes are reloaded until `watch` is stopped or dropped
+/// let watch = watcher.watch(router.clone());
+///
+/// fn page(req: Request, data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+///    Box::new(future::ok(
+///        Response::new()
+///            .with_status(StatusCode::Ok)
+///            .with_body("page"),
+///    ))
+/// }
+/// ```
+pub struct ConfigWatcher {
+    path: PathBuf,
+    registry: HandlerRegistry,
+    builder: Box<Fn() -> RouteBuilder + Send>,
+    interval: Duration,
+    on_reload: Option<OnReload>,
+}
+
+// OnReload is the callback called with the outcome of each reload.
+type OnReload = Box<Fn(Result<(), &ConfigError>) + Send>;
+
+impl ConfigWatcher {
+    /// Constructs a new `ConfigWatcher` for the config file, resolving the names of handlers and
+    /// middleware using the registry.
+    pub fn new<P: Into<PathBuf>>(path: P, registry: HandlerRegistry) -> Self {
+        ConfigWatcher {
+            path: path.into(),
+            registry,
+            builder: Box::new(RouteBuilder::new),
+            interval: Duration::from_secs(1),
+            on_reload: None,
+        }
+    }
+
+    /// Sets the function returning the `RouteBuilder` the routes of the file are registered on,
+    /// eg. to set a not found handler, global middleware or routes registered in code. A new
+    /// builder is needed for each reload, by default `RouteBuilder::new` is used.
+    pub fn set_builder<F>(mut self, builder: F) -> Self
+    where
+        F: 'static + Send + Fn() -> RouteBuilder,
+    {
+        self.builder = Box::new(builder);
+        self
+    }
+
+    /// Sets how often the file is polled for changes.
+    pub fn set_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the callback called with the outcome of each reload, whether it was swapped in or
+    /// the error the file was rejected with.
+    pub fn on_reload<F>(mut self, on_reload: F) -> Self
+    where
+        F: 'static + Send + Fn(Result<(), &ConfigError>),
+    {
+        self.on_reload = Some(Box::new(on_reload));
+        self
+    }
+
+    /// Reloads the file into the router right away, returning the error it was rejected with, if
+    /// any, in which case the router is left untouched.
+    pub fn reload(&self, router: &SwappableRouter) -> Result<(), ConfigError> {
+        let result = config::read(&self.path)
+            .and_then(|(format, source)| self.swap(router, &source, format));
+        self.report(result.as_ref().map(|_| ()));
+        result
+    }
+
+    // builds the routes described by the source and swaps them in, if they are valid.
+    fn swap(
+        &self,
+        router: &SwappableRouter,
+        source: &str,
+        format: config::ConfigFormat,
+    ) -> Result<(), ConfigError> {
+        let builder = (self.builder)().parse_config(source, format, &self.registry)?;
+        router.swap(builder.try_finalize().map_err(ConfigError::Routes)?);
+        Ok(())
+    }
+
+    fn report(&self, result: Result<(), &ConfigError>) {
+        if let Some(ref on_reload) = self.on_reload {
+            on_reload(result);
+        }
+    }
+
+    /// Watches the file from a background thread, reloading it into the router whenever its
+    /// contents change, until the returned `Watch` is stopped or dropped.
+    ///
+    /// The file as it is when this is called is taken as already loaded; use `reload` beforehand
+    /// to load it.
+    pub fn watch(self, router: SwappableRouter) -> Watch {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let mut last = config::read(&self.path).map(|(_, source)| source).ok();
+        let mut seen = stamp(&self.path);
+
+        let thread = thread::spawn(move || {
+            // whether reading the file failed last time, so that it's only reported once
+            let mut unreadable = false;
+            loop {
+                thread::park_timeout(self.interval);
+                if stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                // the file is left until it has stopped changing, a missing file being reported
+                let current = stamp(&self.path);
+                if current.is_some() && current != seen {
+                    seen = current;
+                    continue;
+                }
+
+                match config::read(&self.path) {
+                    Ok((format, source)) => {
+                        unreadable = false;
+                        if last.as_ref() == Some(&source) {
+                            continue;
+                        }
+                        let result = self.swap(&router, &source, format);
+                        self.report(result.as_ref().map(|_| ()));
+                        last = Some(source);
+                    }
+                    Err(e) => {
+                        if !unreadable {
+                            self.report(Err(&e));
+                        }
+                        unreadable = true;
+                    }
+                }
+            }
+        });
+
+        Watch {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+// returns the size and modification time of the file, if it can be read.
+fn stamp(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    fs::metadata(path)
+        .ok()
+        .map(|m| (m.len(), m.modified().ok()))
+}
+
+/// Watch is a config file being watched by a `ConfigWatcher`, which stops when dropped.
+pub struct Watch {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+    /// Stops watching the file, waiting for a reload in progress to complete.
+    pub fn stop(self) {}
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{future, Future};
+    use hyper::server::{Request, Response};
+    use hyper::{Error, Method, StatusCode};
+    use node::RequestData;
+    use std::env;
+    use std::sync::mpsc;
+
+    fn page(_req: Request, _data: RequestData) -> Box<Future<Item = Response, Error = Error>> {
+        Box::new(future::ok(Response::new()))
+    }
+
+    fn status(router: &SwappableRouter, path: &str) -> StatusCode {
+        let req = Request::new(Method::Get, path.parse().unwrap());
+        let res = router.current().handler.handle(req, RequestData::default());
+        res.wait().unwrap().status()
+    }
+
+    fn route(path: &str) -> String {
+        format!(
+            "[[routes]]\nmethod = \"GET\"\npath = \"{}\"\nhandler = \"page\"\n",
+            path
+        )
+    }
+
+    // replaces the file at once, as the watcher could otherwise read it while it's being written.
+    fn replace(path: &Path, contents: &str) {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents).unwrap();
+        fs::rename(&tmp, path).unwrap();
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let path = env::temp_dir().join(format!("lars-watch-{}.toml", ::std::process::id()));
+        fs::write(&path, route("/a")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let router = SwappableRouter::new(RouteBuilder::new().finalize());
+        let watcher =
+            ConfigWatcher::new(path.clone(), HandlerRegistry::new().handler("page", page))
+                .set_interval(Duration::from_millis(10))
+                .on_reload(move |result| tx.send(result.map_err(|e| e.clone())).unwrap());
+        watcher.reload(&router).unwrap();
+        assert_eq!(rx.recv().unwrap(), Ok(()));
+        assert_eq!(status(&router, "/a"), StatusCode::Ok);
+
+        let watch = watcher.watch(router.clone());
+        replace(&path, &route("/b"));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), Ok(()));
+        assert_eq!(status(&router, "/a"), StatusCode::NotFound);
+        assert_eq!(status(&router, "/b"), StatusCode::Ok);
+
+        // an invalid file is reported, and the previous routes kept
+        replace(&path, &route("/b/:id/:id"));
+        let err = rx
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.line(), Some(1));
+        assert_eq!(status(&router, "/b"), StatusCode::Ok);
+
+        // as is a file without routes, such as one emptied before being written
+        replace(&path, "");
+        let err = rx
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.line(), None);
+        assert_eq!(status(&router, "/b"), StatusCode::Ok);
+
+        watch.stop();
+        replace(&path, &route("/c"));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(status(&router, "/c"), StatusCode::NotFound);
+        let _ = fs::remove_file(&path);
+    }
+}